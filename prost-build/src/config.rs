@@ -123,6 +123,9 @@ impl Config {
     /// Configure the code generator to generate Rust [`bytes::Bytes`][1] fields for Protobuf
     /// [`bytes`][2] type fields.
     ///
+    /// When a message is decoded from a `Bytes` buffer, `Bytes` fields (including repeated ones)
+    /// are sliced out of the input buffer rather than copied.
+    ///
     /// # Arguments
     ///
     /// **`paths`** - paths to specific fields, messages, or packages which should use a Rust
@@ -0,0 +1,52 @@
+use anyhow::{bail, Error};
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::Meta;
+
+use crate::field::{int_attr, set_option};
+
+/// A `bool` field which is encoded as one bit of the message's flags field.
+#[derive(Clone)]
+pub struct Field {
+    pub bit: u32,
+}
+
+impl Field {
+    pub fn new(attrs: &[Meta]) -> Result<Option<Field>, Error> {
+        let mut bit = None;
+
+        let mut unknown_attrs = Vec::new();
+
+        for attr in attrs {
+            if let Some(b) = int_attr("flag", attr)? {
+                set_option(&mut bit, b, "duplicate flag attributes")?;
+            } else {
+                unknown_attrs.push(attr);
+            }
+        }
+
+        let bit = match bit {
+            Some(bit) => bit,
+            None => return Ok(None),
+        };
+
+        match unknown_attrs.len() {
+            0 => (),
+            1 => bail!("unknown attribute for flag field: {:?}", unknown_attrs[0]),
+            _ => bail!("unknown attributes for flag field: {:?}", unknown_attrs),
+        }
+
+        if bit >= 64 {
+            bail!(
+                "flag bit {} is out of range; flag bits must be less than 64",
+                bit
+            );
+        }
+
+        Ok(Some(Field { bit }))
+    }
+
+    pub fn clear(&self, ident: TokenStream) -> TokenStream {
+        quote!(#ident = false)
+    }
+}
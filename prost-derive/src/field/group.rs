@@ -82,7 +82,7 @@ impl Field {
                 ::prost::encoding::group::encode(#tag, &#ident, buf);
             },
             Label::Repeated => quote! {
-                for msg in &#ident {
+                for msg in #ident.iter() {
                     ::prost::encoding::group::encode(#tag, msg, buf);
                 }
             },
@@ -104,7 +104,13 @@ impl Field {
                 ::prost::encoding::group::merge(tag, wire_type, #ident, buf, ctx)
             },
             Label::Repeated => quote! {
-                ::prost::encoding::group::merge_repeated(tag, wire_type, #ident, buf, ctx)
+                ::prost::encoding::group::merge_repeated(
+                    tag,
+                    wire_type,
+                    ::prost::encoding::repeated::as_mut_vec(#ident),
+                    buf,
+                    ctx,
+                )
             },
         }
     }
@@ -128,7 +134,7 @@ impl Field {
         match self.label {
             Label::Optional => quote!(#ident = ::core::option::Option::None),
             Label::Required => quote!(#ident.clear()),
-            Label::Repeated => quote!(#ident.clear()),
+            Label::Repeated => quote!(::prost::encoding::repeated::clear(&mut #ident)),
         }
     }
 }
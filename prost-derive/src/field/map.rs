@@ -51,16 +51,28 @@ pub struct Field {
     pub key_ty: scalar::Ty,
     pub value_ty: ValueTy,
     pub tag: u32,
+    /// Whether the keys are wrapped in `Reverse`, so that a `BTreeMap` is ordered by descending
+    /// key.
+    pub reverse_keys: bool,
 }
 
 impl Field {
     pub fn new(attrs: &[Meta], inferred_tag: Option<u32>) -> Result<Option<Field>, Error> {
         let mut types = None;
         let mut tag = None;
+        let mut reverse_keys = false;
 
         for attr in attrs {
             if let Some(t) = tag_attr(attr)? {
                 set_option(&mut tag, t, "duplicate tag attributes")?;
+            } else if attr.path().is_ident("reverse_keys") {
+                if !matches!(attr, Meta::Path(_)) {
+                    bail!("invalid reverse_keys attribute: {:?}", attr);
+                }
+                if reverse_keys {
+                    bail!("duplicate reverse_keys attributes");
+                }
+                reverse_keys = true;
             } else if let Some(map_ty) = attr
                 .path()
                 .get_ident()
@@ -114,6 +126,7 @@ impl Field {
                 key_ty,
                 value_ty,
                 tag,
+                reverse_keys,
             }),
             _ => None,
         })
@@ -126,9 +139,8 @@ impl Field {
     /// Returns a statement which encodes the map field.
     pub fn encode(&self, ident: TokenStream) -> TokenStream {
         let tag = self.tag;
-        let key_mod = self.key_ty.module();
-        let ke = quote!(::prost::encoding::#key_mod::encode);
-        let kl = quote!(::prost::encoding::#key_mod::encoded_len);
+        let ke = self.key_encode();
+        let kl = self.key_encoded_len();
         let module = self.map_ty.module();
         match &self.value_ty {
             ValueTy::Scalar(scalar::Ty::Enumeration(ty)) => {
@@ -179,8 +191,7 @@ impl Field {
     /// Returns an expression which evaluates to the result of merging a decoded key value pair
     /// into the map.
     pub fn merge(&self, ident: TokenStream) -> TokenStream {
-        let key_mod = self.key_ty.module();
-        let km = quote!(::prost::encoding::#key_mod::merge);
+        let km = self.key_merge();
         let module = self.map_ty.module();
         match &self.value_ty {
             ValueTy::Scalar(scalar::Ty::Enumeration(ty)) => {
@@ -216,8 +227,7 @@ impl Field {
     /// Returns an expression which evaluates to the encoded length of the map.
     pub fn encoded_len(&self, ident: TokenStream) -> TokenStream {
         let tag = self.tag;
-        let key_mod = self.key_ty.module();
-        let kl = quote!(::prost::encoding::#key_mod::encoded_len);
+        let kl = self.key_encoded_len();
         let module = self.map_ty.module();
         match &self.value_ty {
             ValueTy::Scalar(scalar::Ty::Enumeration(ty)) => {
@@ -248,22 +258,69 @@ impl Field {
         }
     }
 
+    /// Returns an expression which evaluates to the key encoding function, unwrapping reversed
+    /// keys.
+    fn key_encode(&self) -> TokenStream {
+        let key_mod = self.key_ty.module();
+        if self.reverse_keys {
+            quote!(|tag, key: &::core::cmp::Reverse<_>, buf| {
+                ::prost::encoding::#key_mod::encode(tag, &key.0, buf)
+            })
+        } else {
+            quote!(::prost::encoding::#key_mod::encode)
+        }
+    }
+
+    /// Returns an expression which evaluates to the key merge function, unwrapping reversed keys.
+    fn key_merge(&self) -> TokenStream {
+        let key_mod = self.key_ty.module();
+        if self.reverse_keys {
+            quote!(|wire_type, key: &mut ::core::cmp::Reverse<_>, buf, ctx| {
+                ::prost::encoding::#key_mod::merge(wire_type, &mut key.0, buf, ctx)
+            })
+        } else {
+            quote!(::prost::encoding::#key_mod::merge)
+        }
+    }
+
+    /// Returns an expression which evaluates to the key encoded length function, unwrapping
+    /// reversed keys.
+    fn key_encoded_len(&self) -> TokenStream {
+        let key_mod = self.key_ty.module();
+        if self.reverse_keys {
+            quote!(|tag, key: &::core::cmp::Reverse<_>| {
+                ::prost::encoding::#key_mod::encoded_len(tag, &key.0)
+            })
+        } else {
+            quote!(::prost::encoding::#key_mod::encoded_len)
+        }
+    }
+
     pub fn clear(&self, ident: TokenStream) -> TokenStream {
         quote!(#ident.clear())
     }
 
     /// Returns methods to embed in the message.
-    pub fn methods(&self, ident: &TokenStream) -> Option<TokenStream> {
+    ///
+    /// `docs` is appended to the documentation of each method.
+    pub fn methods(&self, ident: &TokenStream, docs: &TokenStream) -> Option<TokenStream> {
         if let ValueTy::Scalar(scalar::Ty::Enumeration(ty)) = &self.value_ty {
             let key_ty = self.key_ty.rust_type();
-            let key_ref_ty = self.key_ty.rust_ref_type();
 
             let get = Ident::new(&format!("get_{}", ident), Span::call_site());
             let insert = Ident::new(&format!("insert_{}", ident), Span::call_site());
-            let take_ref = if self.key_ty.is_numeric() {
-                quote!(&)
+            // Reversed keys are looked up by value, since `Reverse<String>` can't be borrowed as
+            // `Reverse<str>`.
+            let (key_ref_ty, lookup, key) = if self.reverse_keys {
+                (
+                    key_ty.clone(),
+                    quote!(&::core::cmp::Reverse(key)),
+                    quote!(::core::cmp::Reverse(key)),
+                )
+            } else if self.key_ty.is_numeric() {
+                (self.key_ty.rust_ref_type(), quote!(&key), quote!(key))
             } else {
-                quote!()
+                (self.key_ty.rust_ref_type(), quote!(key), quote!(key))
             };
 
             let get_doc = format!(
@@ -274,15 +331,17 @@ impl Field {
             let insert_doc = format!("Inserts a key value pair into `{}`.", ident);
             Some(quote! {
                 #[doc=#get_doc]
+                #docs
                 pub fn #get(&self, key: #key_ref_ty) -> ::core::option::Option<#ty> {
-                    self.#ident.get(#take_ref key).cloned().and_then(|x| {
+                    self.#ident.get(#lookup).cloned().and_then(|x| {
                         let result: ::core::result::Result<#ty, _> = ::core::convert::TryFrom::try_from(x);
                         result.ok()
                     })
                 }
                 #[doc=#insert_doc]
+                #docs
                 pub fn #insert(&mut self, key: #key_ty, value: #ty) -> ::core::option::Option<#ty> {
-                    self.#ident.insert(key, value as i32).and_then(|x| {
+                    self.#ident.insert(#key, value as i32).and_then(|x| {
                         let result: ::core::result::Result<#ty, _> = ::core::convert::TryFrom::try_from(x);
                         result.ok()
                     })
@@ -306,6 +365,11 @@ impl Field {
         // A fake field for generating the debug wrapper
         let key_wrapper = fake_scalar(self.key_ty.clone()).debug(quote!(KeyWrapper));
         let key = self.key_ty.rust_type();
+        let (key, key_value) = if self.reverse_keys {
+            (quote!(::core::cmp::Reverse<#key>), quote!(&k.0))
+        } else {
+            (key, quote!(k))
+        };
         let value_wrapper = self.value_ty.debug();
         let libname = self.map_ty.lib();
         let fmt = quote! {
@@ -314,7 +378,7 @@ impl Field {
                 #value_wrapper
                 let mut builder = f.debug_map();
                 for (k, v) in self.0 {
-                    builder.entry(&KeyWrapper(k), &ValueWrapper(v));
+                    builder.entry(&KeyWrapper(#key_value), &ValueWrapper(v));
                 }
                 builder.finish()
             }
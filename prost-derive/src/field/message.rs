@@ -85,7 +85,7 @@ impl Field {
                 ::prost::encoding::message::encode(#tag, &#ident, buf);
             },
             Label::Repeated => quote! {
-                for msg in &#ident {
+                for msg in #ident.iter() {
                     ::prost::encoding::message::encode(#tag, msg, buf);
                 }
             },
@@ -104,7 +104,12 @@ impl Field {
                 ::prost::encoding::message::merge(wire_type, #ident, buf, ctx)
             },
             Label::Repeated => quote! {
-                ::prost::encoding::message::merge_repeated(wire_type, #ident, buf, ctx)
+                ::prost::encoding::message::merge_repeated(
+                    wire_type,
+                    ::prost::encoding::repeated::as_mut_vec(#ident),
+                    buf,
+                    ctx,
+                )
             },
         }
     }
@@ -128,7 +133,7 @@ impl Field {
         match self.label {
             Label::Optional => quote!(#ident = ::core::option::Option::None),
             Label::Required => quote!(#ident.clear()),
-            Label::Repeated => quote!(#ident.clear()),
+            Label::Repeated => quote!(::prost::encoding::repeated::clear(&mut #ident)),
         }
     }
 }
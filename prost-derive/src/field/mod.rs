@@ -1,3 +1,4 @@
+mod flag;
 mod group;
 mod map;
 mod message;
@@ -5,13 +6,17 @@ mod oneof;
 mod scalar;
 
 use std::fmt;
+use std::ops::RangeInclusive;
 use std::slice;
 
 use anyhow::{bail, Error};
-use proc_macro2::TokenStream;
+use proc_macro2::{Span, TokenStream};
 use quote::quote;
 use syn::punctuated::Punctuated;
-use syn::{Attribute, Expr, ExprLit, Lit, LitBool, LitInt, Meta, MetaNameValue, Token};
+use syn::{
+    Attribute, Expr, ExprLit, Ident, Lit, LitBool, LitInt, Meta, MetaNameValue, Token, Type,
+    TypePath,
+};
 
 #[derive(Clone)]
 pub enum Field {
@@ -25,6 +30,10 @@ pub enum Field {
     Oneof(oneof::Field),
     /// A group field.
     Group(group::Field),
+    /// A `bool` field encoded as one bit of the message's flags field.
+    Flag(flag::Field),
+    /// A `PhantomData` marker field, which is not encoded.
+    Phantom,
 }
 
 impl Field {
@@ -33,11 +42,16 @@ impl Field {
     /// If the meta items are invalid, an error will be returned.
     /// If the field should be ignored, `None` is returned.
     pub fn new(attrs: Vec<Attribute>, inferred_tag: Option<u32>) -> Result<Option<Field>, Error> {
-        let attrs = prost_attrs(attrs)?;
+        let attrs = prost_attrs(attrs)?
+            .into_iter()
+            .filter(|attr| !word_attr("id", attr))
+            .collect::<Vec<_>>();
 
         // TODO: check for ignore attribute.
 
-        let field = if let Some(field) = scalar::Field::new(&attrs, inferred_tag)? {
+        let field = if let Some(field) = flag::Field::new(&attrs)? {
+            Field::Flag(field)
+        } else if let Some(field) = scalar::Field::new(&attrs, inferred_tag)? {
             Field::Scalar(field)
         } else if let Some(field) = message::Field::new(&attrs, inferred_tag)? {
             Field::Message(field)
@@ -85,9 +99,38 @@ impl Field {
             Field::Map(ref map) => vec![map.tag],
             Field::Oneof(ref oneof) => oneof.tags.clone(),
             Field::Group(ref group) => vec![group.tag],
+            // Flags are encoded under the message's flags tag.
+            Field::Flag(..) | Field::Phantom => vec![],
+        }
+    }
+
+    /// Returns the bit position of a flag field, or `None` if the field is not a flag.
+    pub fn flag_bit(&self) -> Option<u32> {
+        match *self {
+            Field::Flag(ref flag) => Some(flag.bit),
+            _ => None,
         }
     }
 
+    /// Checks that `ty`, the Rust type of the field, is plausible for its protobuf type.
+    pub fn check_type(&self, ty: &Type) -> Result<(), Error> {
+        match *self {
+            Field::Scalar(ref scalar) => scalar.check_type(ty),
+            _ => Ok(()),
+        }
+    }
+
+    /// Creates the field which the flag fields of a message are encoded as, the bits of a
+    /// `uint64`.
+    pub fn flags(tag: u32) -> Field {
+        let ty = scalar::Ty::Uint64;
+        Field::Scalar(scalar::Field {
+            kind: scalar::Kind::Plain(scalar::DefaultValue::new(&ty)),
+            ty,
+            tag,
+        })
+    }
+
     /// Returns a statement which encodes the field.
     pub fn encode(&self, ident: TokenStream) -> TokenStream {
         match *self {
@@ -96,6 +139,24 @@ impl Field {
             Field::Map(ref map) => map.encode(ident),
             Field::Oneof(ref oneof) => oneof.encode(ident),
             Field::Group(ref group) => group.encode(ident),
+            Field::Flag(..) | Field::Phantom => quote!(),
+        }
+    }
+
+    /// Returns a statement which encodes the field unless its tag is in `skip`.
+    pub fn encode_filtered(&self, ident: TokenStream) -> TokenStream {
+        match *self {
+            Field::Oneof(ref oneof) => oneof.encode_filtered(ident),
+            Field::Flag(..) | Field::Phantom => quote!(),
+            _ => {
+                let tag = self.tags()[0];
+                let encode = self.encode(ident);
+                quote! {
+                    if !skip.contains(&#tag) {
+                        #encode
+                    }
+                }
+            }
         }
     }
 
@@ -108,6 +169,8 @@ impl Field {
             Field::Map(ref map) => map.merge(ident),
             Field::Oneof(ref oneof) => oneof.merge(ident),
             Field::Group(ref group) => group.merge(ident),
+            Field::Flag(..) => unreachable!("flag fields are merged with the flags field"),
+            Field::Phantom => unreachable!("phantom fields are not merged"),
         }
     }
 
@@ -119,6 +182,191 @@ impl Field {
             Field::Message(ref msg) => msg.encoded_len(ident),
             Field::Oneof(ref oneof) => oneof.encoded_len(ident),
             Field::Group(ref group) => group.encoded_len(ident),
+            Field::Flag(..) | Field::Phantom => quote!(0),
+        }
+    }
+
+    /// Returns an expression which evaluates to the result of checking the messages held by the
+    /// field once decoding has finished, or `None` if the field holds no messages.
+    pub fn validate_decoded(&self, ident: TokenStream) -> Option<TokenStream> {
+        let label = match *self {
+            Field::Message(ref message) => message.label,
+            Field::Group(ref group) => group.label,
+            Field::Map(ref map) if map.value_ty == map::ValueTy::Message => {
+                return Some(quote! {
+                    #ident.values().try_for_each(::prost::Message::validate_decoded)
+                });
+            }
+            Field::Oneof(..) => {
+                return Some(quote! {
+                    #ident.as_ref().map_or(
+                        ::core::result::Result::Ok(()),
+                        ::prost::Oneof::validate_decoded,
+                    )
+                });
+            }
+            _ => return None,
+        };
+        Some(match label {
+            Label::Optional => quote! {
+                #ident.as_ref().map_or(
+                    ::core::result::Result::Ok(()),
+                    ::prost::Message::validate_decoded,
+                )
+            },
+            Label::Required => quote!(::prost::Message::validate_decoded(&#ident)),
+            Label::Repeated => quote! {
+                #ident.iter().try_for_each(::prost::Message::validate_decoded)
+            },
+        })
+    }
+
+    /// Returns a statement which pushes the tag and encoded length of the field onto `sizes`, if
+    /// the field would be encoded.
+    pub fn field_size(&self, ident: TokenStream) -> TokenStream {
+        match *self {
+            Field::Oneof(ref oneof) => oneof.field_size(ident),
+            Field::Flag(..) | Field::Phantom => quote!(),
+            _ => {
+                let tag = self.tags()[0];
+                let encoded_len = self.encoded_len(ident);
+                quote! {
+                    let len = #encoded_len;
+                    if len != 0 {
+                        sizes.push((#tag, len));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns a statement which inserts the field of `self` into `map` as a dynamic value, and a
+    /// match arm which converts a dynamic `value` with the field's tag back into the field of
+    /// `message`.
+    pub fn dynamic(&self, ident: &TokenStream) -> Result<(TokenStream, TokenStream), Error> {
+        let (tag, optional) = match *self {
+            Field::Scalar(ref scalar) => (
+                scalar.tag,
+                matches!(
+                    scalar.kind,
+                    scalar::Kind::Optional(..) | scalar::Kind::OptionalPacked
+                ),
+            ),
+            Field::Message(ref message) => (message.tag, message.label == Label::Optional),
+            Field::Group(ref group) => (group.tag, group.label == Label::Optional),
+            Field::Phantom => return Ok((quote!(), quote!())),
+            Field::Map(..) => bail!("dynamic conversion does not support map fields"),
+            Field::Oneof(..) => bail!("dynamic conversion does not support oneof fields"),
+            Field::Flag(..) => bail!("dynamic conversion does not support flag fields"),
+        };
+
+        let convert = quote! {
+            ::prost::Dynamic::from_value(value).map_err(|error| error.with_tag(#tag))?
+        };
+        Ok(if optional {
+            (
+                quote! {
+                    if let ::core::option::Option::Some(ref value) = self.#ident {
+                        map.insert(#tag, ::prost::Dynamic::to_value(value));
+                    }
+                },
+                quote!(#tag => message.#ident = ::core::option::Option::Some(#convert),),
+            )
+        } else {
+            (
+                quote!(map.insert(#tag, ::prost::Dynamic::to_value(&self.#ident));),
+                quote!(#tag => message.#ident = #convert,),
+            )
+        })
+    }
+
+    /// Returns an expression which folds the field's tags and wire types into the schema
+    /// fingerprint `fingerprint`.
+    pub fn fingerprint(&self, fingerprint: TokenStream) -> TokenStream {
+        let wire_type = match *self {
+            Field::Flag(..) | Field::Phantom => return fingerprint,
+            Field::Oneof(ref oneof) => {
+                let ty = &oneof.ty;
+                return quote! {
+                    ::prost::encoding::fingerprint_nested(#fingerprint, <#ty as ::prost::Oneof>::SCHEMA_FINGERPRINT)
+                };
+            }
+            _ => self.wire_type(),
+        };
+        let tag = self.tags()[0];
+        quote! {
+            ::prost::encoding::fingerprint_field(
+                #fingerprint,
+                #tag,
+                ::prost::encoding::WireType::#wire_type,
+            )
+        }
+    }
+
+    /// Returns the wire type of a field which has a single tag.
+    fn wire_type(&self) -> Ident {
+        match *self {
+            Field::Scalar(ref scalar) => scalar.wire_type(),
+            Field::Message(..) | Field::Map(..) => Ident::new("LengthDelimited", Span::call_site()),
+            Field::Group(..) => Ident::new("StartGroup", Span::call_site()),
+            Field::Oneof(..) | Field::Flag(..) | Field::Phantom => {
+                unreachable!("field has no wire type of its own")
+            }
+        }
+    }
+
+    /// Returns expressions which evaluate to the `FieldDescriptor` of each tag of the field named
+    /// `name`, paired with the tags.
+    pub fn descriptors(&self, name: &str) -> Vec<(u32, TokenStream)> {
+        let is_repeated = match *self {
+            Field::Scalar(ref scalar) => matches!(
+                scalar.kind,
+                scalar::Kind::Repeated | scalar::Kind::Packed | scalar::Kind::OptionalPacked
+            ),
+            Field::Message(ref message) => message.label == Label::Repeated,
+            Field::Group(ref group) => group.label == Label::Repeated,
+            Field::Map(..) => true,
+            Field::Flag(..) | Field::Phantom => return vec![],
+            Field::Oneof(ref oneof) => {
+                // The oneof lists its variants in tag order.
+                let ty = &oneof.ty;
+                let mut tags = oneof.tags.clone();
+                tags.sort_unstable();
+                return tags
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, tag)| {
+                        (
+                            tag,
+                            quote!(<#ty as ::prost::Oneof>::FIELD_DESCRIPTORS[#index]),
+                        )
+                    })
+                    .collect();
+            }
+        };
+        let tag = self.tags()[0];
+        let wire_type = self.wire_type();
+        vec![(
+            tag,
+            quote! {
+                ::prost::FieldDescriptor {
+                    tag: #tag,
+                    wire_type: ::prost::encoding::WireType::#wire_type,
+                    name: #name,
+                    is_repeated: #is_repeated,
+                    is_oneof: false,
+                }
+            },
+        )]
+    }
+
+    /// Returns the largest number of bytes the field can occupy when encoded, or `None` if the
+    /// encoded length is unbounded.
+    pub fn max_encoded_len(&self) -> Option<usize> {
+        match *self {
+            Field::Scalar(ref scalar) => scalar.max_encoded_len(),
+            Field::Flag(..) | Field::Phantom => Some(0),
+            _ => None,
         }
     }
 
@@ -130,12 +378,16 @@ impl Field {
             Field::Map(ref map) => map.clear(ident),
             Field::Oneof(ref oneof) => oneof.clear(ident),
             Field::Group(ref group) => group.clear(ident),
+            Field::Flag(ref flag) => flag.clear(ident),
+            Field::Phantom => quote!(#ident = ::core::marker::PhantomData),
         }
     }
 
     pub fn default(&self) -> TokenStream {
         match *self {
             Field::Scalar(ref scalar) => scalar.default(),
+            Field::Flag(..) => quote!(false),
+            Field::Phantom => quote!(::core::marker::PhantomData),
             _ => quote!(::core::default::Default::default()),
         }
     }
@@ -165,10 +417,17 @@ impl Field {
         }
     }
 
-    pub fn methods(&self, ident: &TokenStream) -> Option<TokenStream> {
+    /// Returns methods to embed in the message. The field's own documentation, `docs`, is appended
+    /// to the documentation of each method.
+    pub fn methods(&self, ident: &TokenStream, docs: &[Attribute]) -> Option<TokenStream> {
+        let docs = if docs.is_empty() {
+            quote!()
+        } else {
+            quote!(#[doc = ""] #(#docs)*)
+        };
         match *self {
-            Field::Scalar(ref scalar) => scalar.methods(ident),
-            Field::Map(ref map) => map.methods(ident),
+            Field::Scalar(ref scalar) => scalar.methods(ident, &docs),
+            Field::Map(ref map) => map.methods(ident, &docs),
             _ => None,
         }
     }
@@ -241,6 +500,35 @@ fn prost_attrs(attrs: Vec<Attribute>) -> Result<Vec<Meta>, Error> {
     Ok(result)
 }
 
+/// Returns `true` if the field is a `PhantomData` marker with no `prost` attributes, which is
+/// treated as a field which is never encoded.
+pub fn is_phantom(attrs: &[Attribute], ty: &Type) -> bool {
+    if attrs.iter().any(|attr| attr.path().is_ident("prost")) {
+        return false;
+    }
+    match ty {
+        Type::Path(TypePath { path, .. }) => path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "PhantomData"),
+        _ => false,
+    }
+}
+
+/// Returns `true` if the field attributes mark the field as the message's correlation id, with
+/// `#[prost(id)]`.
+pub fn is_id(attrs: &[Attribute]) -> Result<bool, Error> {
+    Ok(prost_attrs(attrs.to_vec())?
+        .iter()
+        .any(|attr| word_attr("id", attr)))
+}
+
+/// Returns the encoded length of the value in LEB128 variable length format, mirroring
+/// `prost::encoding::encoded_len_varint`.
+fn encoded_len_varint(value: u64) -> usize {
+    ((((value | 1).leading_zeros() ^ 63) * 9 + 73) / 64) as usize
+}
+
 pub fn set_option<T>(option: &mut Option<T>, value: T, message: &str) -> Result<(), Error>
 where
     T: fmt::Debug,
@@ -295,7 +583,7 @@ fn bool_attr(key: &str, attr: &Meta) -> Result<Option<bool>, Error> {
 }
 
 /// Checks if an attribute matches a word.
-fn word_attr(key: &str, attr: &Meta) -> bool {
+pub(super) fn word_attr(key: &str, attr: &Meta) -> bool {
     if let Meta::Path(ref path) = *attr {
         path.is_ident(key)
     } else {
@@ -304,7 +592,13 @@ fn word_attr(key: &str, attr: &Meta) -> bool {
 }
 
 pub(super) fn tag_attr(attr: &Meta) -> Result<Option<u32>, Error> {
-    if !attr.path().is_ident("tag") {
+    int_attr("tag", attr)
+}
+
+/// Unpacks an attribute into a (key, integer) pair, returning the integer value.
+/// If the key doesn't match the attribute, `None` is returned.
+pub(super) fn int_attr(key: &str, attr: &Meta) -> Result<Option<u32>, Error> {
+    if !attr.path().is_ident(key) {
         return Ok(None);
     }
     match *attr {
@@ -319,9 +613,9 @@ pub(super) fn tag_attr(attr: &Meta) -> Result<Option<u32>, Error> {
                 .map_err(Error::from)
                 .map(Option::Some),
             Lit::Int(ref lit) => Ok(Some(lit.base10_parse()?)),
-            _ => bail!("invalid tag attribute: {:?}", attr),
+            _ => bail!("invalid {} attribute: {:?}", key, attr),
         },
-        _ => bail!("invalid tag attribute: {:?}", attr),
+        _ => bail!("invalid {} attribute: {:?}", key, attr),
     }
 }
 
@@ -353,3 +647,40 @@ fn tags_attr(attr: &Meta) -> Result<Option<Vec<u32>>, Error> {
         _ => bail!("invalid tag attribute: {:?}", attr),
     }
 }
+
+/// Unpacks a `reserved_tags = "2, 9..=11"` attribute into its ranges of tags. If the attribute
+/// isn't `reserved_tags`, `None` is returned.
+pub(super) fn reserved_tags_attr(attr: &Meta) -> Result<Option<Vec<RangeInclusive<u32>>>, Error> {
+    if !attr.path().is_ident("reserved_tags") {
+        return Ok(None);
+    }
+    let value = match *attr {
+        Meta::NameValue(MetaNameValue {
+            value:
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(ref lit),
+                    ..
+                }),
+            ..
+        }) => lit.value(),
+        _ => bail!("invalid reserved_tags attribute: {:?}", attr),
+    };
+    value
+        .split(',')
+        .map(|item| {
+            let item = item.trim();
+            let range = match item.split_once("..=") {
+                Some((start, end)) => start
+                    .trim()
+                    .parse()
+                    .and_then(|start| end.trim().parse().map(|end| start..=end)),
+                None => item.parse().map(|tag| tag..=tag),
+            };
+            match range {
+                Ok(range) if !range.is_empty() => Ok(range),
+                _ => bail!("invalid reserved_tags attribute: {:?}", item),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+}
@@ -64,12 +64,24 @@ impl Field {
     /// Returns a statement which encodes the oneof field.
     pub fn encode(&self, ident: TokenStream) -> TokenStream {
         quote! {
-            if let Some(ref oneof) = #ident {
+            if let ::core::option::Option::Some(ref oneof) = #ident {
                 oneof.encode(buf)
             }
         }
     }
 
+    /// Returns a statement which encodes the oneof field unless the tag of its current variant is
+    /// in `skip`.
+    pub fn encode_filtered(&self, ident: TokenStream) -> TokenStream {
+        quote! {
+            if let ::core::option::Option::Some(ref oneof) = #ident {
+                if !skip.contains(&::prost::Oneof::tag(oneof)) {
+                    oneof.encode(buf)
+                }
+            }
+        }
+    }
+
     /// Returns an expression which evaluates to the result of decoding the oneof field.
     pub fn merge(&self, ident: TokenStream) -> TokenStream {
         let ty = &self.ty;
@@ -86,6 +98,16 @@ impl Field {
         }
     }
 
+    /// Returns a statement which pushes the tag and encoded length of the oneof field's current
+    /// variant onto `sizes`, if it is set.
+    pub fn field_size(&self, ident: TokenStream) -> TokenStream {
+        quote! {
+            if let ::core::option::Option::Some(ref oneof) = #ident {
+                sizes.push((::prost::Oneof::tag(oneof), oneof.encoded_len()));
+            }
+        }
+    }
+
     pub fn clear(&self, ident: TokenStream) -> TokenStream {
         quote!(#ident = ::core::option::Option::None)
     }
@@ -3,7 +3,10 @@ use std::fmt;
 use anyhow::{anyhow, bail, Error};
 use proc_macro2::{Span, TokenStream};
 use quote::{quote, ToTokens, TokenStreamExt};
-use syn::{parse_str, Expr, ExprLit, Ident, Index, Lit, LitByteStr, Meta, MetaNameValue, Path};
+use syn::{
+    parse_str, Expr, ExprLit, GenericArgument, Ident, Index, Lit, LitByteStr, Meta, MetaNameValue,
+    Path, PathArguments, Type, TypePath,
+};
 
 use crate::field::{bool_attr, set_option, tag_attr, Label};
 
@@ -64,18 +67,20 @@ impl Field {
         )?;
 
         let kind = match (label, packed, has_default) {
-            (None, Some(true), _)
-            | (Some(Label::Optional), Some(true), _)
-            | (Some(Label::Required), Some(true), _) => {
-                bail!("packed attribute may only be applied to repeated fields");
+            (None, Some(true), _) | (Some(Label::Required), Some(true), _) => {
+                bail!("packed attribute may only be applied to repeated or optional fields");
             }
-            (Some(Label::Repeated), Some(true), _) if !ty.is_numeric() => {
+            (Some(Label::Repeated), Some(true), _) | (Some(Label::Optional), Some(true), _)
+                if !ty.is_numeric() =>
+            {
                 bail!("packed attribute may only be applied to numeric types");
             }
-            (Some(Label::Repeated), _, true) => {
+            (Some(Label::Repeated), _, true) | (Some(Label::Optional), Some(true), true) => {
                 bail!("repeated fields may not have a default value");
             }
 
+            (Some(Label::Optional), Some(true), false) => Kind::OptionalPacked,
+
             (None, _, _) => Kind::Plain(default),
             (Some(Label::Optional), _, _) => Kind::Optional(default),
             (Some(Label::Required), _, _) => Kind::Required(default),
@@ -97,7 +102,9 @@ impl Field {
                 }
                 Kind::Optional(..) => bail!("invalid optional attribute on oneof field"),
                 Kind::Required(..) => bail!("invalid required attribute on oneof field"),
-                Kind::Packed | Kind::Repeated => bail!("invalid repeated attribute on oneof field"),
+                Kind::Packed | Kind::Repeated | Kind::OptionalPacked => {
+                    bail!("invalid repeated attribute on oneof field")
+                }
             }
         } else {
             Ok(None)
@@ -109,16 +116,16 @@ impl Field {
         let encode_fn = match self.kind {
             Kind::Plain(..) | Kind::Optional(..) | Kind::Required(..) => quote!(encode),
             Kind::Repeated => quote!(encode_repeated),
-            Kind::Packed => quote!(encode_packed),
+            Kind::Packed | Kind::OptionalPacked => quote!(encode_packed),
         };
         let encode_fn = quote!(::prost::encoding::#module::#encode_fn);
         let tag = self.tag;
 
         match self.kind {
             Kind::Plain(ref default) => {
-                let default = default.typed();
+                let is_set = self.is_set(&ident, default);
                 quote! {
-                    if #ident != #default {
+                    if #is_set {
                         #encode_fn(#tag, &#ident, buf);
                     }
                 }
@@ -131,6 +138,22 @@ impl Field {
             Kind::Required(..) | Kind::Repeated | Kind::Packed => quote! {
                 #encode_fn(#tag, &#ident, buf);
             },
+            // An empty list is written as a zero-length packed field, which `encode_packed` would
+            // otherwise omit.
+            Kind::OptionalPacked => quote! {
+                if let ::core::option::Option::Some(ref values) = #ident {
+                    if values.is_empty() {
+                        ::prost::encoding::encode_key(
+                            #tag,
+                            ::prost::encoding::WireType::LengthDelimited,
+                            buf,
+                        );
+                        ::prost::encoding::encode_varint(0, buf);
+                    } else {
+                        #encode_fn(#tag, values, buf);
+                    }
+                }
+            },
         }
     }
 
@@ -140,20 +163,29 @@ impl Field {
         let module = self.ty.module();
         let merge_fn = match self.kind {
             Kind::Plain(..) | Kind::Optional(..) | Kind::Required(..) => quote!(merge),
-            Kind::Repeated | Kind::Packed => quote!(merge_repeated),
+            Kind::Repeated | Kind::Packed | Kind::OptionalPacked => quote!(merge_repeated),
         };
         let merge_fn = quote!(::prost::encoding::#module::#merge_fn);
 
         match self.kind {
-            Kind::Plain(..) | Kind::Required(..) | Kind::Repeated | Kind::Packed => quote! {
+            Kind::Plain(..) | Kind::Required(..) => quote! {
                 #merge_fn(wire_type, #ident, buf, ctx)
             },
-            Kind::Optional(..) => quote! {
-                #merge_fn(wire_type,
-                          #ident.get_or_insert_with(::core::default::Default::default),
-                          buf,
-                          ctx)
+            Kind::Repeated | Kind::Packed => quote! {
+                #merge_fn(wire_type, ::prost::encoding::repeated::as_mut_vec(#ident), buf, ctx)
             },
+            Kind::Optional(..) | Kind::OptionalPacked => {
+                let empty = match self.ty {
+                    Ty::Bytes(..) => quote!(::prost::encoding::bytes::empty),
+                    _ => quote!(::core::default::Default::default),
+                };
+                quote! {
+                    #merge_fn(wire_type,
+                              #ident.get_or_insert_with(#empty),
+                              buf,
+                              ctx)
+                }
+            }
         }
     }
 
@@ -163,16 +195,16 @@ impl Field {
         let encoded_len_fn = match self.kind {
             Kind::Plain(..) | Kind::Optional(..) | Kind::Required(..) => quote!(encoded_len),
             Kind::Repeated => quote!(encoded_len_repeated),
-            Kind::Packed => quote!(encoded_len_packed),
+            Kind::Packed | Kind::OptionalPacked => quote!(encoded_len_packed),
         };
         let encoded_len_fn = quote!(::prost::encoding::#module::#encoded_len_fn);
         let tag = self.tag;
 
         match self.kind {
             Kind::Plain(ref default) => {
-                let default = default.typed();
+                let is_set = self.is_set(&ident, default);
                 quote! {
-                    if #ident != #default {
+                    if #is_set {
                         #encoded_len_fn(#tag, &#ident)
                     } else {
                         0
@@ -185,6 +217,39 @@ impl Field {
             Kind::Required(..) | Kind::Repeated | Kind::Packed => quote! {
                 #encoded_len_fn(#tag, &#ident)
             },
+            Kind::OptionalPacked => quote! {
+                #ident.as_ref().map_or(0, |values| {
+                    if values.is_empty() {
+                        ::prost::encoding::key_len(#tag) + 1
+                    } else {
+                        #encoded_len_fn(#tag, values)
+                    }
+                })
+            },
+        }
+    }
+
+    /// Returns an expression which is true if the field is set to a value other than its default.
+    fn is_set(&self, ident: &TokenStream, default: &DefaultValue) -> TokenStream {
+        match (&self.ty, default) {
+            // Go through `BytesAdapter`, so that any adapter type can be used for the field,
+            // including those with an empty state other than the empty slice.
+            (Ty::Bytes(..), DefaultValue::Bytes(value)) if value.is_empty() => {
+                quote!(!::prost::encoding::bytes::is_empty(&#ident))
+            }
+            (Ty::Bytes(..), _) => {
+                let default = default.typed();
+                quote!(::core::convert::AsRef::<[u8]>::as_ref(&#ident) != #default)
+            }
+            // Compare as `str`, so that any `StringAdapter` type can be used for the field.
+            (Ty::String, _) => {
+                let default = default.typed();
+                quote!(::core::convert::AsRef::<str>::as_ref(&#ident) != #default)
+            }
+            _ => {
+                let default = default.typed();
+                quote!(#ident != #default)
+            }
         }
     }
 
@@ -193,12 +258,17 @@ impl Field {
             Kind::Plain(ref default) | Kind::Required(ref default) => {
                 let default = default.typed();
                 match self.ty {
-                    Ty::String | Ty::Bytes(..) => quote!(#ident.clear()),
+                    Ty::String => quote!(::prost::encoding::string::clear(&mut #ident)),
+                    Ty::Bytes(..) => quote!(::prost::encoding::bytes::clear(&mut #ident)),
                     _ => quote!(#ident = #default),
                 }
             }
-            Kind::Optional(_) => quote!(#ident = ::core::option::Option::None),
-            Kind::Repeated | Kind::Packed => quote!(#ident.clear()),
+            Kind::Optional(_) | Kind::OptionalPacked => {
+                quote!(#ident = ::core::option::Option::None)
+            }
+            Kind::Repeated | Kind::Packed => {
+                quote!(::prost::encoding::repeated::clear(&mut #ident))
+            }
         }
     }
 
@@ -206,8 +276,69 @@ impl Field {
     pub fn default(&self) -> TokenStream {
         match self.kind {
             Kind::Plain(ref value) | Kind::Required(ref value) => value.owned(),
-            Kind::Optional(_) => quote!(::core::option::Option::None),
-            Kind::Repeated | Kind::Packed => quote!(::prost::alloc::vec::Vec::new()),
+            Kind::Optional(_) | Kind::OptionalPacked => quote!(::core::option::Option::None),
+            Kind::Repeated | Kind::Packed => quote!(::core::default::Default::default()),
+        }
+    }
+
+    /// Checks that the Rust type of the field is plausible for its protobuf type, to report an
+    /// obvious mismatch such as a `fixed32` field of type `String` more clearly than the type
+    /// errors in the generated code would.
+    ///
+    /// Only primitive types and `String` are checked, once unwrapped from the `Option` and `Vec`
+    /// the field's label calls for. Any other type is assumed to be correct.
+    pub fn check_type(&self, ty: &Type) -> Result<(), Error> {
+        let ty = match self.kind {
+            Kind::Plain(..) | Kind::Required(..) => Some(ty),
+            Kind::Optional(..) => type_argument(ty, "Option"),
+            Kind::Repeated | Kind::Packed => type_argument(ty, "Vec"),
+            Kind::OptionalPacked => {
+                type_argument(ty, "Option").and_then(|ty| type_argument(ty, "Vec"))
+            }
+        };
+        let name = match ty.and_then(primitive_name) {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        let expected = match self.ty {
+            // Strings and bytes can be held in many types, but never in a number.
+            Ty::String | Ty::Bytes(..) if name == "String" => return Ok(()),
+            Ty::String => "String".to_string(),
+            Ty::Bytes(..) => "Vec<u8>".to_string(),
+            _ => self.ty.rust_ref_type().to_string(),
+        };
+        if name != expected {
+            let attr = match self.ty {
+                Ty::Enumeration(..) => "enumeration",
+                _ => self.ty.as_str(),
+            };
+            bail!(
+                "`{}` fields must have type `{}`, not `{}`",
+                attr,
+                expected,
+                name
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the `WireType` variant the field is encoded with.
+    pub fn wire_type(&self) -> Ident {
+        match self.kind {
+            Kind::Packed | Kind::OptionalPacked => Ident::new("LengthDelimited", Span::call_site()),
+            _ => self.ty.wire_type(),
+        }
+    }
+
+    /// Returns the largest number of bytes the field can occupy when encoded, including the key,
+    /// or `None` if the encoded length is unbounded.
+    pub fn max_encoded_len(&self) -> Option<usize> {
+        match self.kind {
+            Kind::Plain(..) | Kind::Optional(..) | Kind::Required(..) => {
+                let key_len = super::encoded_len_varint(u64::from(self.tag << 3));
+                self.ty.max_encoded_value_len().map(|len| key_len + len)
+            }
+            Kind::Repeated | Kind::Packed | Kind::OptionalPacked => None,
         }
     }
 
@@ -240,6 +371,9 @@ impl Field {
         let inner_ty = self.ty.rust_type();
         match self.kind {
             Kind::Plain(_) | Kind::Required(_) => self.debug_inner(wrapper_name),
+            // Only enumerations need wrapping; other values are formatted as they are, whatever
+            // their Rust type.
+            _ if !matches!(self.ty, Ty::Enumeration(_)) => self.debug_inner(wrapper_name),
             Kind::Optional(_) => quote! {
                 struct #wrapper_name<'a>(&'a ::core::option::Option<#inner_ty>);
                 impl<'a> ::core::fmt::Debug for #wrapper_name<'a> {
@@ -249,9 +383,23 @@ impl Field {
                     }
                 }
             },
+            Kind::OptionalPacked => quote! {
+                struct #wrapper_name<'a>(
+                    &'a ::core::option::Option<::prost::alloc::vec::Vec<#inner_ty>>,
+                );
+                impl<'a> ::core::fmt::Debug for #wrapper_name<'a> {
+                    fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
+                        #wrapper
+                        let values = self.0.as_ref().map(|values| {
+                            values.iter().map(Inner).collect::<::prost::alloc::vec::Vec<_>>()
+                        });
+                        ::core::fmt::Debug::fmt(&values, f)
+                    }
+                }
+            },
             Kind::Repeated | Kind::Packed => {
                 quote! {
-                    struct #wrapper_name<'a>(&'a ::prost::alloc::vec::Vec<#inner_ty>);
+                    struct #wrapper_name<'a>(&'a [#inner_ty]);
                     impl<'a> ::core::fmt::Debug for #wrapper_name<'a> {
                         fn fmt(&self, f: &mut ::core::fmt::Formatter) -> ::core::fmt::Result {
                             let mut vec_builder = f.debug_list();
@@ -268,7 +416,9 @@ impl Field {
     }
 
     /// Returns methods to embed in the message.
-    pub fn methods(&self, ident: &TokenStream) -> Option<TokenStream> {
+    ///
+    /// `docs` is appended to the documentation of each method.
+    pub fn methods(&self, ident: &TokenStream, docs: &TokenStream) -> Option<TokenStream> {
         let mut ident_str = ident.to_string();
         if ident_str.starts_with("r#") {
             ident_str = ident_str.split_off(2);
@@ -295,11 +445,13 @@ impl Field {
                     );
                     quote! {
                         #[doc=#get_doc]
+                        #docs
                         pub fn #get(&self) -> #ty {
                             ::core::convert::TryFrom::try_from(self.#ident).unwrap_or(#default)
                         }
 
                         #[doc=#set_doc]
+                        #docs
                         pub fn #set(&mut self, value: #ty) {
                             self.#ident = value as i32;
                         }
@@ -313,6 +465,7 @@ impl Field {
                     );
                     quote! {
                         #[doc=#get_doc]
+                        #docs
                         pub fn #get(&self) -> #ty {
                             self.#ident.and_then(|x| {
                                 let result: ::core::result::Result<#ty, _> = ::core::convert::TryFrom::try_from(x);
@@ -321,11 +474,13 @@ impl Field {
                         }
 
                         #[doc=#set_doc]
+                        #docs
                         pub fn #set(&mut self, value: #ty) {
                             self.#ident = ::core::option::Option::Some(value as i32);
                         }
                     }
                 }
+                Kind::OptionalPacked => return None,
                 Kind::Repeated | Kind::Packed => {
                     let iter_doc = format!(
                         "Returns an iterator which yields the valid enum values contained in `{}`.",
@@ -335,6 +490,7 @@ impl Field {
                     let push_doc = format!("Appends the provided enum value to `{}`.", ident_str);
                     quote! {
                         #[doc=#iter_doc]
+                        #docs
                         pub fn #get(&self) -> ::core::iter::FilterMap<
                             ::core::iter::Cloned<::core::slice::Iter<i32>>,
                             fn(i32) -> ::core::option::Option<#ty>,
@@ -345,8 +501,9 @@ impl Field {
                             })
                         }
                         #[doc=#push_doc]
+                        #docs
                         pub fn #push(&mut self, value: #ty) {
-                            self.#ident.push(value as i32);
+                            ::prost::encoding::repeated::as_mut_vec(&mut self.#ident).push(value as i32);
                         }
                     }
                 }
@@ -367,6 +524,7 @@ impl Field {
 
             Some(quote! {
                 #[doc=#get_doc]
+                #docs
                 pub fn #get(&self) -> #ty {
                     match self.#ident {
                         #match_some
@@ -565,6 +723,31 @@ impl Ty {
         }
     }
 
+    /// Returns the name of the `WireType` variant values of this type are encoded with.
+    pub fn wire_type(&self) -> Ident {
+        let wire_type = match *self {
+            Ty::Fixed32 | Ty::Sfixed32 | Ty::Float => "ThirtyTwoBit",
+            Ty::Fixed64 | Ty::Sfixed64 | Ty::Double => "SixtyFourBit",
+            Ty::String | Ty::Bytes(..) => "LengthDelimited",
+            _ => "Varint",
+        };
+        Ident::new(wire_type, Span::call_site())
+    }
+
+    /// Returns the largest encoded length of a value of this type, excluding the key, or `None`
+    /// for length delimited types.
+    pub fn max_encoded_value_len(&self) -> Option<usize> {
+        match *self {
+            // Negative 32-bit values are sign extended to ten bytes.
+            Ty::Int32 | Ty::Int64 | Ty::Uint64 | Ty::Sint64 | Ty::Enumeration(..) => Some(10),
+            Ty::Uint32 | Ty::Sint32 => Some(5),
+            Ty::Bool => Some(1),
+            Ty::Fixed32 | Ty::Sfixed32 | Ty::Float => Some(4),
+            Ty::Fixed64 | Ty::Sfixed64 | Ty::Double => Some(8),
+            Ty::String | Ty::Bytes(..) => None,
+        }
+    }
+
     /// Returns false if the scalar type is length delimited (i.e., `string` or `bytes`).
     pub fn is_numeric(&self) -> bool {
         !matches!(self, Ty::String | Ty::Bytes(..))
@@ -596,6 +779,8 @@ pub enum Kind {
     Repeated,
     /// A packed repeated scalar field.
     Packed,
+    /// A packed repeated scalar field which distinguishes an absent list from an empty one.
+    OptionalPacked,
 }
 
 /// Scalar Protobuf field default value.
@@ -781,11 +966,11 @@ impl DefaultValue {
     pub fn owned(&self) -> TokenStream {
         match *self {
             DefaultValue::String(ref value) if value.is_empty() => {
-                quote!(::prost::alloc::string::String::new())
+                quote!(::core::default::Default::default())
             }
             DefaultValue::String(ref value) => quote!(#value.into()),
             DefaultValue::Bytes(ref value) if value.is_empty() => {
-                quote!(::core::default::Default::default())
+                quote!(::prost::encoding::bytes::empty())
             }
             DefaultValue::Bytes(ref value) => {
                 let lit = LitByteStr::new(value, Span::call_site());
@@ -825,3 +1010,37 @@ impl ToTokens for DefaultValue {
         }
     }
 }
+
+/// Returns the type argument of `ty` if it is a path ending in `wrapper` with a single type
+/// argument, such as `Option<T>` or `::std::vec::Vec<T>`.
+fn type_argument<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    let segment = match ty {
+        Type::Path(TypePath { qself: None, path }) => path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != wrapper {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(arguments) if arguments.args.len() == 1 => {
+            match &arguments.args[0] {
+                GenericArgument::Type(ty) => Some(ty),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Returns the name of `ty` if it is a primitive numeric type, `bool` or `String`.
+fn primitive_name(ty: &Type) -> Option<&'static str> {
+    const NAMES: &[&str] = &[
+        "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize",
+        "f32", "f64", "bool", "String",
+    ];
+    let ident = match ty {
+        Type::Path(TypePath { qself: None, path }) => path.get_ident()?,
+        _ => return None,
+    };
+    NAMES.iter().copied().find(|&name| ident == name)
+}
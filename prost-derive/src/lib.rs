@@ -11,8 +11,8 @@ use proc_macro::TokenStream;
 use proc_macro2::Span;
 use quote::quote;
 use syn::{
-    punctuated::Punctuated, Data, DataEnum, DataStruct, DeriveInput, Expr, Fields, FieldsNamed,
-    FieldsUnnamed, Ident, Index, Variant,
+    punctuated::Punctuated, Data, DataEnum, DataStruct, DeriveInput, Expr, ExprLit, Fields,
+    FieldsNamed, FieldsUnnamed, Ident, Index, Lit, Meta, Token, Variant,
 };
 
 mod field;
@@ -23,11 +23,67 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
 
     let ident = input.ident;
 
-    syn::custom_keyword!(skip_debug);
-    let skip_debug = input
-        .attrs
-        .into_iter()
-        .any(|a| a.path().is_ident("prost") && a.parse_args::<skip_debug>().is_ok());
+    // Message attributes may be given together in one `#[prost(...)]`, or across several.
+    let mut skip_debug = false;
+    let mut validate = false;
+    // Encodes the fields in declaration order rather than tag order, for peers which expect it.
+    let mut declaration_order = false;
+    let mut dynamic = false;
+    // Generates a `descriptor` function describing the message's fields.
+    let mut descriptor = false;
+    // Generate the `field_sizes`, and `encode_framed` and `decode_framed` functions.
+    let mut field_sizes = false;
+    let mut framed = false;
+    // The window of tags which the message's fields may use, if restricted.
+    let mut min_tag = None;
+    let mut max_tag = None;
+    // Retired tags which the message's fields may not use.
+    let mut reserved_tags = None;
+    // The tag of the field which the message's flag fields are encoded in together.
+    let mut flags_tag = None;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("prost")) {
+        let metas = attr.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
+        for meta in &metas {
+            if field::word_attr("skip_debug", meta) {
+                field::set_bool(&mut skip_debug, "duplicate skip_debug attributes")?;
+            } else if field::word_attr("validate", meta) {
+                field::set_bool(&mut validate, "duplicate validate attributes")?;
+            } else if field::word_attr("declaration_order", meta) {
+                field::set_bool(
+                    &mut declaration_order,
+                    "duplicate declaration_order attributes",
+                )?;
+            } else if field::word_attr("dynamic", meta) {
+                field::set_bool(&mut dynamic, "duplicate dynamic attributes")?;
+            } else if field::word_attr("descriptor", meta) {
+                field::set_bool(&mut descriptor, "duplicate descriptor attributes")?;
+            } else if field::word_attr("field_sizes", meta) {
+                field::set_bool(&mut field_sizes, "duplicate field_sizes attributes")?;
+            } else if field::word_attr("framed", meta) {
+                field::set_bool(&mut framed, "duplicate framed attributes")?;
+            } else if let Some(tag) = field::int_attr("min_tag", meta)? {
+                field::set_option(&mut min_tag, tag, "duplicate min_tag attributes")?;
+            } else if let Some(tag) = field::int_attr("max_tag", meta)? {
+                field::set_option(&mut max_tag, tag, "duplicate max_tag attributes")?;
+            } else if let Some(ranges) = field::reserved_tags_attr(meta)? {
+                field::set_option(
+                    &mut reserved_tags,
+                    ranges,
+                    "duplicate reserved_tags attributes",
+                )?;
+            } else if let Some(tag) = flags_attr(meta)? {
+                field::set_option(&mut flags_tag, tag, "duplicate flags attributes")?;
+            } else {
+                bail!("invalid message attribute: {}", quote!(#meta));
+            }
+        }
+    }
+
+    let tag_window = min_tag.unwrap_or(0)..=max_tag.unwrap_or(u32::MAX);
+    let reserved_tags = reserved_tags.unwrap_or_default();
+    if tag_window.is_empty() {
+        bail!("message {} has a min_tag greater than its max_tag", ident);
+    }
 
     let variant_data = match input.data {
         Data::Struct(variant_data) => variant_data,
@@ -57,6 +113,10 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
     };
 
     let mut next_tag: u32 = 1;
+    // Field documentation in declaration order, carried over to the generated methods.
+    let mut field_docs = Vec::new();
+    // Fields marked as the message's correlation id, with their types.
+    let mut id_fields = Vec::new();
     let mut fields = fields
         .into_iter()
         .enumerate()
@@ -68,9 +128,40 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
                 };
                 quote!(#index)
             });
-            match Field::new(field.attrs, Some(next_tag)) {
+            let docs = field
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("doc"))
+                .cloned()
+                .collect::<Vec<_>>();
+            let is_id = match field::is_id(&field.attrs) {
+                Ok(is_id) => is_id,
+                Err(err) => {
+                    return Some(Err(err.context(format!(
+                        "invalid message field {}.{}",
+                        ident, field_ident
+                    ))))
+                }
+            };
+            let ty = field.ty;
+            let field = if field::is_phantom(&field.attrs, &ty) {
+                Ok(Some(Field::Phantom))
+            } else {
+                Field::new(field.attrs, Some(next_tag))
+            };
+            match field {
                 Ok(Some(field)) => {
+                    if let Err(err) = field.check_type(&ty) {
+                        return Some(Err(err.context(format!(
+                            "invalid message field {}.{}",
+                            ident, field_ident
+                        ))));
+                    }
                     next_tag = field.tags().iter().max().map(|t| t + 1).unwrap_or(next_tag);
+                    field_docs.push(docs);
+                    if is_id {
+                        id_fields.push((field_ident.clone(), ty));
+                    }
                     Some(Ok((field_ident, field)))
                 }
                 Ok(None) => None,
@@ -88,12 +179,69 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
     // TODO: This encodes oneof fields in the position of their lowest tag,
     // regardless of the currently occupied variant, is that consequential?
     // See: https://developers.google.com/protocol-buffers/docs/encoding#order
-    fields.sort_by_key(|(_, field)| field.tags().into_iter().min().unwrap());
+    fields.sort_by_key(|(_, field)| field.tags().into_iter().min());
     let fields = fields;
 
+    // Flag fields are encoded together, as the bits of a single `uint64` field.
+    let flags = fields
+        .iter()
+        .filter_map(|(field_ident, field)| field.flag_bit().map(|bit| (field_ident, bit)))
+        .collect::<Vec<_>>();
+    let flags_field = match (flags_tag, flags.is_empty()) {
+        (Some(tag), false) => Some(Field::flags(tag)),
+        (None, false) => {
+            return Err(syn::Error::new_spanned(
+                &ident,
+                format!(
+                    "message {} has flag fields, but no `flags(tag = \"...\")` attribute",
+                    ident
+                ),
+            )
+            .into())
+        }
+        (Some(_), true) => {
+            return Err(syn::Error::new_spanned(
+                &ident,
+                format!(
+                    "message {} has a flags attribute, but no flag fields",
+                    ident
+                ),
+            )
+            .into())
+        }
+        (None, true) => None,
+    };
+    let mut bits = flags.iter().map(|&(_, bit)| bit).collect::<Vec<_>>();
+    bits.sort_unstable();
+    bits.dedup();
+    if bits.len() != flags.len() {
+        bail!("message {} has flag fields with duplicate bits", ident);
+    }
+    let flags_value = {
+        let flags = flags
+            .iter()
+            .map(|(field_ident, bit)| quote!(if self.#field_ident { 1u64 << #bit } else { 0 }));
+        quote!(0u64 #(| #flags)*)
+    };
+    // The position of the flags field among the other fields, in tag order.
+    let flags_position = flags_tag.map(|tag| {
+        fields
+            .iter()
+            .take_while(|(_, field)| field.tags().into_iter().all(|t| t < tag))
+            .count()
+    });
+    // The position the flags field is encoded at. In declaration order, the flags field has no
+    // declaration of its own and is encoded last.
+    let flags_encode_position = if declaration_order {
+        flags_tag.map(|_| fields.len())
+    } else {
+        flags_position
+    };
+
     let mut tags = fields
         .iter()
         .flat_map(|(_, field)| field.tags())
+        .chain(flags_tag)
         .collect::<Vec<_>>();
     let num_tags = tags.len();
     tags.sort_unstable();
@@ -102,29 +250,134 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
         bail!("message {} has fields with duplicate tags", ident);
     }
 
-    let encoded_len = fields
+    if let Some(tag) = flags_tag.filter(|tag| !tag_window.contains(tag)) {
+        bail!(
+            "message {} has flags tag {} outside of its tag window {}..={}",
+            ident,
+            tag,
+            tag_window.start(),
+            tag_window.end()
+        );
+    }
+    let is_reserved = |tag: &u32| reserved_tags.iter().any(|range| range.contains(tag));
+    if let Some(tag) = flags_tag.filter(is_reserved) {
+        bail!("message {} has reserved flags tag {}", ident, tag);
+    }
+    for (field_ident, field) in &fields {
+        if let Some(tag) = field.tags().into_iter().find(is_reserved) {
+            bail!("field {}.{} has reserved tag {}", ident, field_ident, tag);
+        }
+        if let Some(tag) = field
+            .tags()
+            .into_iter()
+            .find(|tag| !tag_window.contains(tag))
+        {
+            bail!(
+                "field {}.{} has tag {} outside of the message's tag window {}..={}",
+                ident,
+                field_ident,
+                tag,
+                tag_window.start(),
+                tag_window.end()
+            );
+        }
+    }
+
+    let mut encoded_len = fields
         .iter()
-        .map(|(field_ident, field)| field.encoded_len(quote!(self.#field_ident)));
+        .map(|(field_ident, field)| field.encoded_len(quote!(self.#field_ident)))
+        .collect::<Vec<_>>();
 
-    let encode = fields
+    let encode_fields = if declaration_order {
+        &unsorted_fields
+    } else {
+        &fields
+    };
+    let mut encode = encode_fields
+        .iter()
+        .map(|(field_ident, field)| field.encode(quote!(self.#field_ident)))
+        .collect::<Vec<_>>();
+    let mut encode_filtered = encode_fields
         .iter()
-        .map(|(field_ident, field)| field.encode(quote!(self.#field_ident)));
+        .map(|(field_ident, field)| field.encode_filtered(quote!(self.#field_ident)))
+        .collect::<Vec<_>>();
 
-    let merge = fields.iter().map(|(field_ident, field)| {
-        let merge = field.merge(quote!(value));
-        let tags = field.tags().into_iter().map(|tag| quote!(#tag));
-        let tags = Itertools::intersperse(tags, quote!(|));
+    // Flag and phantom fields have no tags of their own to be merged under.
+    let mut merge = fields
+        .iter()
+        .filter(|(_, field)| !field.tags().is_empty())
+        .map(|(field_ident, field)| {
+            let merge = field.merge(quote!(value));
+            let tags = field.tags().into_iter().map(|tag| quote!(#tag));
+            let tags = Itertools::intersperse(tags, quote!(|));
 
-        quote! {
-            #(#tags)* => {
-                let mut value = &mut self.#field_ident;
-                #merge.map_err(|mut error| {
-                    error.push(STRUCT_NAME, stringify!(#field_ident));
+            quote! {
+                #(#tags)* => {
+                    let mut value = &mut self.#field_ident;
+                    #merge.map_err(|mut error| {
+                        error.push_field(STRUCT_NAME, stringify!(#field_ident), tag, wire_type);
+                        error
+                    })
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+
+    if let (Some(field), Some(tag), Some(position)) =
+        (&flags_field, flags_tag, flags_encode_position)
+    {
+        let flag_idents = flags.iter().map(|&(field_ident, _)| field_ident);
+        let flag_bits = flags.iter().map(|&(_, bit)| bit);
+        let flags_merge = field.merge(quote!(&mut flags));
+        merge.push(quote! {
+            #tag => {
+                let mut flags = 0u64;
+                #flags_merge.map_err(|mut error| {
+                    error.push_field(STRUCT_NAME, "flags", tag, wire_type);
                     error
-                })
+                })?;
+                #(self.#flag_idents = flags & (1u64 << #flag_bits) != 0;)*
+                ::core::result::Result::Ok(())
             },
-        }
-    });
+        });
+
+        let flags_encoded_len = field.encoded_len(quote!(flags));
+        encoded_len.push(quote!({
+            let flags = #flags_value;
+            #flags_encoded_len
+        }));
+
+        let flags_encode = field.encode(quote!(flags));
+        encode.insert(
+            position,
+            quote!({
+                let flags = #flags_value;
+                #flags_encode
+            }),
+        );
+        encode_filtered.insert(
+            position,
+            quote! {
+                if !skip.contains(&#tag) {
+                    let flags = #flags_value;
+                    #flags_encode
+                }
+            },
+        );
+    }
+
+    let field_size_entries = fields
+        .iter()
+        .map(|(field_ident, field)| field.field_size(quote!(self.#field_ident)))
+        .chain(flags_field.iter().map(|field| {
+            let field_size = field.field_size(quote!(flags));
+            quote! {
+                let flags = #flags_value;
+                #field_size
+            }
+        }))
+        .map(|field_size| quote!({ #field_size }))
+        .collect::<Vec<_>>();
 
     let struct_name = if fields.is_empty() {
         quote!()
@@ -147,7 +400,7 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
             #(#default)*
         }}
     } else {
-        let default = fields.iter().map(|(_, field)| {
+        let default = unsorted_fields.iter().map(|(_, field)| {
             let value = field.default();
             quote!(#value,)
         });
@@ -156,19 +409,257 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
         )}
     };
 
-    let methods = fields
+    let max_tag = tags.last().copied().unwrap_or(0);
+    let reserved_tags = reserved_tags.iter().map(|range| {
+        let (start, end) = (range.start(), range.end());
+        quote!(::core::ops::RangeInclusive::new(#start, #end))
+    });
+    let max_encoded_len = fields
+        .iter()
+        .map(|(_, field)| field)
+        .chain(&flags_field)
+        .map(Field::max_encoded_len)
+        .sum::<Option<usize>>();
+    let max_encoded_len = match max_encoded_len {
+        Some(len) => quote!(::core::option::Option::Some(#len)),
+        None => quote!(::core::option::Option::None),
+    };
+    let mut fingerprint = fields
         .iter()
-        .flat_map(|(field_ident, field)| field.methods(field_ident))
+        .map(|(_, field)| field.fingerprint(quote!(fingerprint)))
         .collect::<Vec<_>>();
-    let methods = if methods.is_empty() {
+    if let (Some(field), Some(position)) = (&flags_field, flags_position) {
+        fingerprint.insert(position, field.fingerprint(quote!(fingerprint)));
+    }
+    let schema_fingerprint = quote! {{
+        let fingerprint = ::prost::encoding::FINGERPRINT_SEED;
+        #(let fingerprint = #fingerprint;)*
+        fingerprint
+    }};
+
+    let field_sizes = if field_sizes {
+        let doc = format!(
+            "Returns the encoded length of each field of `{}`, including its keys, paired with \
+             the field's tag in tag order.\n\n\
+             Fields which would not be encoded are left out, and a oneof field is reported under \
+             the tag of its current variant. The lengths sum to the message's `encoded_len`.",
+            ident
+        );
+        quote! {
+            #[doc=#doc]
+            #[allow(unused_mut)]
+            pub fn field_sizes(&self) -> ::prost::alloc::vec::Vec<(u32, usize)> {
+                let mut sizes = ::prost::alloc::vec::Vec::new();
+                #(#field_size_entries)*
+                sizes.sort_unstable_by_key(|&(tag, _)| tag);
+                sizes
+            }
+        }
+    } else {
         quote!()
+    };
+
+    let framed = if framed {
+        quote! {
+            /// Encodes the message with a header of a magic number and the message's
+            /// `SCHEMA_FINGERPRINT`, so that decoding it with a different schema fails.
+            pub fn encode_framed(&self) -> ::prost::alloc::vec::Vec<u8> {
+                ::prost::encoding::encode_framed(
+                    <Self as ::prost::MessageSchema>::SCHEMA_FINGERPRINT,
+                    self,
+                )
+            }
+
+            /// Decodes a message encoded by `encode_framed`, failing if its header is invalid or
+            /// it was encoded with a different schema.
+            pub fn decode_framed<B>(buf: B) -> ::core::result::Result<Self, ::prost::DecodeError>
+            where
+                B: ::prost::bytes::Buf,
+            {
+                ::prost::encoding::decode_framed(
+                    <Self as ::prost::MessageSchema>::SCHEMA_FINGERPRINT,
+                    buf,
+                )
+            }
+        }
     } else {
+        quote!()
+    };
+
+    // The descriptor is built in a constant, which can't refer to the message's type parameters,
+    // as the descriptors of a generic oneof would.
+    let has_oneofs = fields
+        .iter()
+        .any(|(_, field)| matches!(field, Field::Oneof(..)));
+    let descriptor = if !descriptor {
+        quote!()
+    } else if generics.params.is_empty() || !has_oneofs {
+        let mut descriptors = fields
+            .iter()
+            .flat_map(|(field_ident, field)| {
+                field.descriptors(field_ident.to_string().trim_start_matches("r#"))
+            })
+            .chain(
+                flags_field
+                    .iter()
+                    .flat_map(|field| field.descriptors("flags")),
+            )
+            .collect::<Vec<_>>();
+        descriptors.sort_by_key(|&(tag, _)| tag);
+        let descriptors = descriptors.into_iter().map(|(_, descriptor)| descriptor);
+        let name = ident.to_string();
+        let descriptor_doc = format!("Returns a description of the fields of `{}`.", ident);
         quote! {
-            #[allow(dead_code)]
-            impl #impl_generics #ident #ty_generics #where_clause {
-                #(#methods)*
+            #[doc=#descriptor_doc]
+            pub fn descriptor() -> &'static ::prost::MessageDescriptor {
+                const DESCRIPTOR: ::prost::MessageDescriptor = ::prost::MessageDescriptor {
+                    name: #name,
+                    fields: &[#(#descriptors),*],
+                };
+                &DESCRIPTOR
             }
         }
+    } else {
+        bail!(
+            "message {} is generic and has oneof fields, so it can't have a descriptor",
+            ident
+        );
+    };
+
+    let correlation_id = match &id_fields[..] {
+        [] => quote!(),
+        [(field_ident, ty)] => quote! {
+            /// Returns the field which identifies this message, for correlating it across logs and
+            /// traces.
+            pub fn correlation_id(&self) -> &#ty {
+                &self.#field_ident
+            }
+        },
+        _ => bail!("message {} has more than one id field", ident),
+    };
+
+    let methods = unsorted_fields
+        .iter()
+        .zip(&field_docs)
+        .flat_map(|((field_ident, field), docs)| field.methods(field_ident, docs))
+        .collect::<Vec<_>>();
+    let methods = quote! {
+        #[allow(dead_code)]
+        impl #impl_generics #ident #ty_generics #where_clause {
+            #field_sizes
+
+            #framed
+
+            #descriptor
+
+            #correlation_id
+
+            #(#methods)*
+        }
+    };
+
+    // Only messages which opt in with `#[prost(validate)]` check anything, walking their nested
+    // messages before checking the message itself, once decoding has finished. Other messages
+    // keep the default `validate_decoded`, which does nothing.
+    let validate_decoded = if validate {
+        let validate_fields = fields.iter().filter_map(|(field_ident, field)| {
+            let validate = field.validate_decoded(quote!(self.#field_ident))?;
+            Some(quote! {
+                #validate.map_err(|mut error| {
+                    error.push(stringify!(#ident), stringify!(#field_ident));
+                    error
+                })?;
+            })
+        });
+        quote! {
+            fn validate_decoded(&self) -> ::core::result::Result<(), ::prost::DecodeError> {
+                #(#validate_fields)*
+                ::prost::Validate::validate(self).map_err(|error| {
+                    error.with_kind(::prost::DecodeErrorKind::ValidationFailed)
+                })
+            }
+        }
+    } else {
+        quote!()
+    };
+
+    let dynamic = if dynamic {
+        if !generics.params.is_empty() {
+            bail!(
+                "message {} is generic, so it can't be converted to dynamic values",
+                ident
+            );
+        }
+        let (to_dynamic, from_dynamic): (Vec<_>, Vec<_>) = unsorted_fields
+            .iter()
+            .map(|(field_ident, field)| {
+                field.dynamic(field_ident).map_err(|err| {
+                    err.context(format!("invalid message field {}.{}", ident, field_ident))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .unzip();
+        let to_dynamic_doc = format!(
+            "Converts `{}` to its fields as dynamic values, keyed by tag.\n\n\
+             Optional fields which are not set are left out.",
+            ident
+        );
+        let from_dynamic_doc = format!(
+            "Converts dynamic values keyed by tag back to `{}`.\n\n\
+             Fields which are missing from the map are left at their defaults. An error is \
+             returned if the map has a tag which is not a field, or a value of the wrong kind.",
+            ident
+        );
+        quote! {
+            impl #ident {
+                #[doc=#to_dynamic_doc]
+                pub fn to_dynamic(
+                    &self,
+                ) -> ::prost::alloc::collections::BTreeMap<u32, ::prost::DynValue> {
+                    let mut map = ::prost::alloc::collections::BTreeMap::new();
+                    #(#to_dynamic)*
+                    map
+                }
+
+                #[doc=#from_dynamic_doc]
+                pub fn from_dynamic(
+                    map: ::prost::alloc::collections::BTreeMap<u32, ::prost::DynValue>,
+                ) -> ::core::result::Result<Self, ::prost::ConversionError> {
+                    let mut message = <Self as ::core::default::Default>::default();
+                    for (tag, value) in map {
+                        match tag {
+                            #(#from_dynamic)*
+                            _ => {
+                                return ::core::result::Result::Err(
+                                    ::prost::ConversionError::new("unknown field").with_tag(tag),
+                                )
+                            }
+                        }
+                    }
+                    ::core::result::Result::Ok(message)
+                }
+            }
+
+            impl ::prost::Dynamic for #ident {
+                fn to_value(&self) -> ::prost::DynValue {
+                    ::prost::DynValue::Message(self.to_dynamic())
+                }
+
+                fn from_value(
+                    value: ::prost::DynValue,
+                ) -> ::core::result::Result<Self, ::prost::ConversionError> {
+                    match value {
+                        ::prost::DynValue::Message(map) => Self::from_dynamic(map),
+                        _ => ::core::result::Result::Err(
+                            ::prost::ConversionError::new("expected a message"),
+                        ),
+                    }
+                }
+            }
+        }
+    } else {
+        quote!()
     };
 
     let expanded = quote! {
@@ -178,6 +669,17 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
                 #(#encode)*
             }
 
+            #[allow(unused_variables)]
+            fn encode_raw_filtered<B>(
+                &self,
+                skip: &::prost::alloc::collections::BTreeSet<u32>,
+                buf: &mut B,
+            ) -> ::core::result::Result<(), ::prost::DecodeError>
+            where B: ::prost::bytes::BufMut {
+                #(#encode_filtered)*
+                ::core::result::Result::Ok(())
+            }
+
             #[allow(unused_variables)]
             fn merge_field<B>(
                 &mut self,
@@ -199,6 +701,8 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
                 0 #(+ #encoded_len)*
             }
 
+            #validate_decoded
+
             fn clear(&mut self) {
                 #(#clear;)*
             }
@@ -209,6 +713,14 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
                 #default
             }
         }
+
+        impl #impl_generics ::prost::MessageSchema for #ident #ty_generics #where_clause {
+            const MAX_ENCODED_LEN: ::core::option::Option<usize> = #max_encoded_len;
+            const MAX_TAG: u32 = #max_tag;
+            const RESERVED_TAGS: &'static [::core::ops::RangeInclusive<u32>] =
+                &[#(#reserved_tags),*];
+            const SCHEMA_FINGERPRINT: u64 = #schema_fingerprint;
+        }
     };
     let expanded = if skip_debug {
         expanded
@@ -249,14 +761,53 @@ fn try_message(input: TokenStream) -> Result<TokenStream, Error> {
         #expanded
 
         #methods
+
+        #dynamic
     };
 
     Ok(expanded.into())
 }
 
+/// Unpacks a `flags(tag = "...")` message attribute into the tag of the flags field. If the
+/// attribute isn't `flags`, `None` is returned.
+fn flags_attr(attr: &Meta) -> Result<Option<u32>, Error> {
+    if !attr.path().is_ident("flags") {
+        return Ok(None);
+    }
+    let invalid = || {
+        syn::Error::new_spanned(
+            attr,
+            "invalid flags attribute; expected `flags(tag = \"...\")`",
+        )
+    };
+    let list = match *attr {
+        Meta::List(ref list) => list,
+        _ => return Err(invalid().into()),
+    };
+    let attrs = list
+        .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+        .map_err(|_| invalid())?;
+    match attrs
+        .iter()
+        .map(field::tag_attr)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?[..]
+    {
+        [Some(tag)] => Ok(Some(tag)),
+        _ => Err(invalid().into()),
+    }
+}
+
 #[proc_macro_derive(Message, attributes(prost))]
 pub fn message(input: TokenStream) -> TokenStream {
-    try_message(input).unwrap()
+    match try_message(input) {
+        Ok(output) => output,
+        // Errors which point at the offending tokens are reported there as compile errors.
+        Err(error) => match error.downcast::<syn::Error>() {
+            Ok(error) => error.to_compile_error().into(),
+            Err(error) => panic!("{:?}", error),
+        },
+    }
 }
 
 fn try_enumeration(input: TokenStream) -> Result<TokenStream, Error> {
@@ -300,7 +851,32 @@ fn try_enumeration(input: TokenStream) -> Result<TokenStream, Error> {
 
     let default = variants[0].0.clone();
 
-    let is_valid = variants.iter().map(|(_, value)| quote!(#value => true));
+    // When every value is a small non-negative literal, validity is checked against a bitmask of
+    // the values instead of matching them in turn.
+    let mask = variants
+        .iter()
+        .map(|(_, value)| match value {
+            Expr::Lit(ExprLit {
+                lit: Lit::Int(lit), ..
+            }) => lit.base10_parse::<u32>().ok().filter(|&bit| bit < 64),
+            _ => None,
+        })
+        .try_fold(0u64, |mask, bit| Some(mask | 1 << bit?));
+    let is_valid = match mask {
+        Some(mask) => quote! {
+            const VALID: u64 = #mask;
+            (0..64).contains(&value) && (VALID >> value) & 1 == 1
+        },
+        None => {
+            let is_valid = variants.iter().map(|(_, value)| quote!(#value => true));
+            quote! {
+                match value {
+                    #(#is_valid,)*
+                    _ => false,
+                }
+            }
+        }
+    };
     let from = variants
         .iter()
         .map(|(variant, value)| quote!(#value => ::core::option::Option::Some(#ident::#variant)));
@@ -309,7 +885,18 @@ fn try_enumeration(input: TokenStream) -> Result<TokenStream, Error> {
         .iter()
         .map(|(variant, value)| quote!(#value => ::core::result::Result::Ok(#ident::#variant)));
 
+    let all_variants = variants.iter().map(|(variant, _)| quote!(#ident::#variant));
+    let all_numbers = variants.iter().map(|(_, value)| value);
+
     let is_valid_doc = format!("Returns `true` if `value` is a variant of `{}`.", ident);
+    let all_variants_doc = format!(
+        "Returns every variant of `{}`, in declaration order.",
+        ident
+    );
+    let all_numbers_doc = format!(
+        "Returns the value of every variant of `{}`, in declaration order.",
+        ident
+    );
     let from_i32_doc = format!(
         "Converts an `i32` to a `{}`, or `None` if `value` is not a valid variant.",
         ident
@@ -319,10 +906,17 @@ fn try_enumeration(input: TokenStream) -> Result<TokenStream, Error> {
         impl #impl_generics #ident #ty_generics #where_clause {
             #[doc=#is_valid_doc]
             pub fn is_valid(value: i32) -> bool {
-                match value {
-                    #(#is_valid,)*
-                    _ => false,
-                }
+                #is_valid
+            }
+
+            #[doc=#all_variants_doc]
+            pub fn all_variants() -> &'static [#ident] {
+                &[#(#all_variants,)*]
+            }
+
+            #[doc=#all_numbers_doc]
+            pub fn all_numbers() -> &'static [i32] {
+                &[#(#all_numbers,)*]
             }
 
             #[deprecated = "Use the TryFrom<i32> implementation instead"]
@@ -407,7 +1001,15 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream, Error> {
             bail!("Oneof enum variants must have a single field");
         }
         match Field::new_oneof(attrs)? {
-            Some(field) => fields.push((variant_ident, field)),
+            Some(field) => {
+                if let Err(err) = field.check_type(&variant_fields[0].ty) {
+                    return Err(err.context(format!(
+                        "invalid oneof variant {}::{}",
+                        ident, variant_ident
+                    )));
+                }
+                fields.push((variant_ident, field))
+            }
             None => bail!("invalid oneof variant: oneof variants may not be ignored"),
         }
     }
@@ -431,6 +1033,12 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream, Error> {
         panic!("invalid oneof {}: variants have duplicate tags", ident);
     }
 
+    let mut fingerprint_fields = fields.iter().map(|(_, field)| field).collect::<Vec<_>>();
+    fingerprint_fields.sort_by_key(|field| field.tags()[0]);
+    let fingerprint = fingerprint_fields
+        .iter()
+        .map(|field| field.fingerprint(quote!(fingerprint)));
+
     let encode = fields.iter().map(|(variant_ident, field)| {
         let encode = field.encode(quote!(*value));
         quote!(#ident::#variant_ident(ref value) => { #encode })
@@ -439,6 +1047,7 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream, Error> {
     let merge = fields.iter().map(|(variant_ident, field)| {
         let tag = field.tags()[0];
         let merge = field.merge(quote!(value));
+        let default = field.default();
         quote! {
             #tag => {
                 match field {
@@ -446,7 +1055,7 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream, Error> {
                         #merge
                     },
                     _ => {
-                        let mut owned_value = ::core::default::Default::default();
+                        let mut owned_value = #default;
                         let value = &mut owned_value;
                         #merge.map(|_| *field = ::core::option::Option::Some(#ident::#variant_ident(owned_value)))
                     },
@@ -460,7 +1069,54 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream, Error> {
         quote!(#ident::#variant_ident(ref value) => #encoded_len)
     });
 
+    let mut descriptors = fields
+        .iter()
+        .flat_map(|(variant_ident, field)| field.descriptors(&variant_ident.to_string()))
+        .collect::<Vec<_>>();
+    descriptors.sort_by_key(|&(tag, _)| tag);
+    let descriptors = descriptors.into_iter().map(|(_, descriptor)| {
+        quote!(::prost::FieldDescriptor {
+            is_oneof: true,
+            ..#descriptor
+        })
+    });
+
+    let validate_decoded =
+        fields.iter().map(
+            |(variant_ident, field)| match field.validate_decoded(quote!(*value)) {
+                Some(validate) => quote!(#ident::#variant_ident(ref value) => #validate),
+                None => quote!(#ident::#variant_ident(..) => ::core::result::Result::Ok(())),
+            },
+        );
+
+    let tag = fields.iter().map(|(variant_ident, field)| {
+        let tag = field.tags()[0];
+        quote!(#ident::#variant_ident(..) => #tag)
+    });
+
     let expanded = quote! {
+        impl #impl_generics ::prost::Oneof for #ident #ty_generics #where_clause {
+            const SCHEMA_FINGERPRINT: u64 = {
+                let fingerprint = ::prost::encoding::FINGERPRINT_SEED;
+                #(let fingerprint = #fingerprint;)*
+                fingerprint
+            };
+
+            const FIELD_DESCRIPTORS: &'static [::prost::FieldDescriptor] = &[#(#descriptors),*];
+
+            fn tag(&self) -> u32 {
+                match *self {
+                    #(#tag,)*
+                }
+            }
+
+            fn validate_decoded(&self) -> ::core::result::Result<(), ::prost::DecodeError> {
+                match *self {
+                    #(#validate_decoded,)*
+                }
+            }
+        }
+
         impl #impl_generics #ident #ty_generics #where_clause {
             /// Encodes the message to a buffer.
             pub fn encode<B>(&self, buf: &mut B) where B: ::prost::bytes::BufMut {
@@ -492,7 +1148,6 @@ fn try_oneof(input: TokenStream) -> Result<TokenStream, Error> {
                 }
             }
         }
-
     };
     let expanded = if skip_debug {
         expanded
@@ -95,9 +95,10 @@ impl TryFrom<Duration> for time::Duration {
                 duration.nanos as u32,
             ))
         } else {
+            // `unsigned_abs` avoids overflowing when negating `i64::MIN` seconds.
             Err(DurationError::NegativeDuration(time::Duration::new(
-                (-duration.seconds) as u64,
-                (-duration.nanos) as u32,
+                duration.seconds.unsigned_abs(),
+                duration.nanos.unsigned_abs(),
             )))
         }
     }
@@ -238,6 +239,91 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "std")]
+    proptest! {
+        #[test]
+        fn check_duration_seconds_range(
+            seconds in (i64::MAX as u64 - 1_000)..=u64::MAX,
+            nanos in 0u32..1_000_000_000u32,
+        ) {
+            let std_duration = time::Duration::new(seconds, nanos);
+            match Duration::try_from(std_duration) {
+                Ok(prost_duration) => {
+                    prop_assert!(seconds <= i64::MAX as u64);
+                    prop_assert_eq!(prost_duration.seconds as u64, seconds);
+                    prop_assert_eq!(prost_duration.nanos as u32, nanos);
+                    prop_assert_eq!(time::Duration::try_from(prost_duration).unwrap(), std_duration);
+                }
+                Err(error) => {
+                    prop_assert!(seconds > i64::MAX as u64);
+                    prop_assert_eq!(error, DurationError::OutOfRange);
+                }
+            }
+        }
+
+        #[test]
+        fn check_negative_duration_seconds_range(
+            seconds in i64::MIN..=(i64::MIN + 1_000),
+            nanos in -999_999_999i32..=0,
+        ) {
+            let expected = time::Duration::new(seconds.unsigned_abs(), nanos.unsigned_abs());
+            prop_assert_eq!(
+                time::Duration::try_from(Duration { seconds, nanos }),
+                Err(DurationError::NegativeDuration(expected)),
+            );
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn check_duration_extremes() {
+        assert_eq!(
+            Duration::try_from(time::Duration::MAX),
+            Err(DurationError::OutOfRange)
+        );
+        assert_eq!(
+            Duration::try_from(time::Duration::new(i64::MAX as u64, 999_999_999)),
+            Ok(Duration {
+                seconds: i64::MAX,
+                nanos: 999_999_999,
+            })
+        );
+        assert_eq!(
+            Duration::try_from(time::Duration::ZERO),
+            Ok(Duration::default())
+        );
+        assert_eq!(
+            time::Duration::try_from(Duration::default()),
+            Ok(time::Duration::ZERO)
+        );
+
+        // Out of range nanoseconds are carried into the seconds before converting.
+        assert_eq!(
+            time::Duration::try_from(Duration {
+                seconds: 1,
+                nanos: 1_000_000_000,
+            }),
+            Ok(time::Duration::new(2, 0))
+        );
+        assert_eq!(
+            time::Duration::try_from(Duration {
+                seconds: i64::MAX,
+                nanos: 1_000_000_000,
+            }),
+            Ok(time::Duration::new(i64::MAX as u64, 999_999_999))
+        );
+        assert_eq!(
+            time::Duration::try_from(Duration {
+                seconds: i64::MIN,
+                nanos: -1_000_000_000,
+            }),
+            Err(DurationError::NegativeDuration(time::Duration::new(
+                i64::MIN.unsigned_abs(),
+                999_999_999
+            )))
+        );
+    }
+
     #[cfg(feature = "std")]
     #[test]
     fn check_duration_try_from_negative_nanos() {
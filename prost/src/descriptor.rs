@@ -0,0 +1,64 @@
+//! Runtime descriptions of the fields of derived messages.
+
+use crate::encoding::WireType;
+
+/// A description of a message's fields, as returned by the derived `descriptor` function.
+///
+/// Descriptors allow generic tooling, such as inspectors and debuggers, to interpret encoded
+/// messages without knowing their Rust types. The `descriptor` function is generated for messages
+/// with the `#[prost(descriptor)]` attribute, whose fields must not depend on their type
+/// parameters through a oneof.
+///
+/// ```rust
+/// use prost::encoding::WireType;
+/// use prost::Message;
+///
+/// #[derive(Clone, PartialEq, Message)]
+/// #[prost(descriptor)]
+/// struct Point {
+///     #[prost(sint32, tag = "1")]
+///     x: i32,
+///     #[prost(sint32, tag = "2")]
+///     y: i32,
+/// }
+///
+/// let descriptor = Point::descriptor();
+/// assert_eq!(descriptor.name, "Point");
+/// assert_eq!(descriptor.field(2).unwrap().name, "y");
+/// assert_eq!(descriptor.field(2).unwrap().wire_type, WireType::Varint);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MessageDescriptor {
+    /// The name of the message's Rust type.
+    pub name: &'static str,
+    /// The message's fields, in tag order.
+    ///
+    /// Each variant of a oneof field is listed under its own tag.
+    pub fields: &'static [FieldDescriptor],
+}
+
+impl MessageDescriptor {
+    /// Returns the field with the given tag, if the message has one.
+    pub fn field(&self, tag: u32) -> Option<&'static FieldDescriptor> {
+        let fields = self.fields;
+        fields
+            .binary_search_by_key(&tag, |field| field.tag)
+            .ok()
+            .map(|index| &fields[index])
+    }
+}
+
+/// A description of one field of a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FieldDescriptor {
+    /// The field's tag.
+    pub tag: u32,
+    /// The wire type the field is encoded with. Packed fields are length delimited.
+    pub wire_type: WireType,
+    /// The name of the field, or of the variant for a oneof field.
+    pub name: &'static str,
+    /// Whether the field can occur more than once, as repeated and map fields can.
+    pub is_repeated: bool,
+    /// Whether the field is a variant of a oneof.
+    pub is_oneof: bool,
+}
@@ -0,0 +1,278 @@
+//! Conversion of messages to and from maps of dynamically typed values.
+
+#[cfg(not(feature = "std"))]
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+
+use bytes::Bytes;
+
+/// A dynamically typed field value, for bridges to dynamically typed languages.
+///
+/// Messages annotated with `#[prost(dynamic)]` get a `to_dynamic` method, which converts the
+/// message to a map of these values keyed by field tag, and a `from_dynamic` function, which
+/// converts such a map back into the message.
+///
+/// ```rust
+/// use prost::{DynValue, Message};
+///
+/// #[derive(Clone, PartialEq, Message)]
+/// #[prost(dynamic)]
+/// struct Point {
+///     #[prost(sint32, tag = "1")]
+///     x: i32,
+///     #[prost(sint32, repeated, tag = "2")]
+///     history: Vec<i32>,
+/// }
+///
+/// let point = Point { x: 3, history: vec![1, 2] };
+/// let map = point.to_dynamic();
+/// assert_eq!(map[&1], DynValue::Int(3));
+/// assert_eq!(map[&2], DynValue::List(vec![DynValue::Int(1), DynValue::Int(2)]));
+/// assert_eq!(Point::from_dynamic(map), Ok(point));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum DynValue {
+    /// A signed integer, from a signed integer or enumeration field.
+    Int(i64),
+    /// An unsigned integer, from an unsigned integer field.
+    Uint(u64),
+    /// A floating-point number.
+    Float(f64),
+    /// A boolean.
+    Bool(bool),
+    /// A string.
+    String(String),
+    /// A byte string.
+    Bytes(Vec<u8>),
+    /// A nested message, as its fields keyed by tag.
+    Message(BTreeMap<u32, DynValue>),
+    /// The values of a repeated field.
+    List(Vec<DynValue>),
+}
+
+/// A field value which can be converted to and from a [`DynValue`].
+pub trait Dynamic: Sized {
+    /// Converts the value to a dynamic value.
+    fn to_value(&self) -> DynValue;
+
+    /// Converts a dynamic value back to a value of this type, or returns an error if it is of a
+    /// different kind.
+    fn from_value(value: DynValue) -> Result<Self, ConversionError>;
+}
+
+/// An error converting a [`DynValue`] to a field or message.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConversionError {
+    description: &'static str,
+    tags: Vec<u32>,
+}
+
+impl ConversionError {
+    /// Creates a new `ConversionError` with a description of the problem.
+    pub fn new(description: &'static str) -> ConversionError {
+        ConversionError {
+            description,
+            tags: Vec::new(),
+        }
+    }
+
+    /// Adds the tag of the field containing the value which failed to convert.
+    ///
+    /// Meant to be used only by `Message` implementations.
+    #[doc(hidden)]
+    pub fn with_tag(mut self, tag: u32) -> ConversionError {
+        self.tags.push(tag);
+        self
+    }
+
+    /// Returns the tags of the fields where conversion failed, starting with the innermost field.
+    pub fn tag_path(&self) -> &[u32] {
+        &self.tags
+    }
+}
+
+impl fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("failed to convert dynamic message: ")?;
+        for tag in &self.tags {
+            write!(f, "field {}: ", tag)?;
+        }
+        f.write_str(self.description)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ConversionError {}
+
+macro_rules! dynamic_int {
+    ($ty:ty, $variant:ident, $wide:ty) => {
+        impl Dynamic for $ty {
+            fn to_value(&self) -> DynValue {
+                DynValue::$variant(<$wide>::from(*self))
+            }
+
+            fn from_value(value: DynValue) -> Result<$ty, ConversionError> {
+                let value = match value {
+                    DynValue::Int(value) => <$ty>::try_from(value).ok(),
+                    DynValue::Uint(value) => <$ty>::try_from(value).ok(),
+                    _ => return Err(ConversionError::new("expected an integer")),
+                };
+                value.ok_or_else(|| ConversionError::new("integer out of range"))
+            }
+        }
+    };
+}
+
+dynamic_int!(i32, Int, i64);
+dynamic_int!(i64, Int, i64);
+dynamic_int!(u32, Uint, u64);
+dynamic_int!(u64, Uint, u64);
+
+impl Dynamic for f32 {
+    fn to_value(&self) -> DynValue {
+        DynValue::Float(f64::from(*self))
+    }
+
+    fn from_value(value: DynValue) -> Result<f32, ConversionError> {
+        f64::from_value(value).map(|value| value as f32)
+    }
+}
+
+impl Dynamic for f64 {
+    fn to_value(&self) -> DynValue {
+        DynValue::Float(*self)
+    }
+
+    fn from_value(value: DynValue) -> Result<f64, ConversionError> {
+        match value {
+            DynValue::Float(value) => Ok(value),
+            _ => Err(ConversionError::new("expected a float")),
+        }
+    }
+}
+
+impl Dynamic for bool {
+    fn to_value(&self) -> DynValue {
+        DynValue::Bool(*self)
+    }
+
+    fn from_value(value: DynValue) -> Result<bool, ConversionError> {
+        match value {
+            DynValue::Bool(value) => Ok(value),
+            _ => Err(ConversionError::new("expected a bool")),
+        }
+    }
+}
+
+impl Dynamic for String {
+    fn to_value(&self) -> DynValue {
+        DynValue::String(self.clone())
+    }
+
+    fn from_value(value: DynValue) -> Result<String, ConversionError> {
+        match value {
+            DynValue::String(value) => Ok(value),
+            _ => Err(ConversionError::new("expected a string")),
+        }
+    }
+}
+
+impl Dynamic for Box<str> {
+    fn to_value(&self) -> DynValue {
+        DynValue::String(String::from(&**self))
+    }
+
+    fn from_value(value: DynValue) -> Result<Box<str>, ConversionError> {
+        String::from_value(value).map(String::into_boxed_str)
+    }
+}
+
+impl Dynamic for Vec<u8> {
+    fn to_value(&self) -> DynValue {
+        DynValue::Bytes(self.clone())
+    }
+
+    fn from_value(value: DynValue) -> Result<Vec<u8>, ConversionError> {
+        match value {
+            DynValue::Bytes(value) => Ok(value),
+            _ => Err(ConversionError::new("expected bytes")),
+        }
+    }
+}
+
+impl Dynamic for Bytes {
+    fn to_value(&self) -> DynValue {
+        DynValue::Bytes(self.to_vec())
+    }
+
+    fn from_value(value: DynValue) -> Result<Bytes, ConversionError> {
+        Vec::<u8>::from_value(value).map(Bytes::from)
+    }
+}
+
+impl<T: Dynamic> Dynamic for Vec<T> {
+    fn to_value(&self) -> DynValue {
+        DynValue::List(self.iter().map(T::to_value).collect())
+    }
+
+    fn from_value(value: DynValue) -> Result<Vec<T>, ConversionError> {
+        match value {
+            DynValue::List(values) => values.into_iter().map(T::from_value).collect(),
+            _ => Err(ConversionError::new("expected a list")),
+        }
+    }
+}
+
+impl<T: Dynamic> Dynamic for Box<T> {
+    fn to_value(&self) -> DynValue {
+        (**self).to_value()
+    }
+
+    fn from_value(value: DynValue) -> Result<Box<T>, ConversionError> {
+        T::from_value(value).map(Box::new)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn integers() {
+        assert_eq!(u32::MAX.to_value(), DynValue::Uint(u32::MAX.into()));
+        assert_eq!(i32::from_value(DynValue::Uint(7)), Ok(7));
+        assert_eq!(u64::from_value(DynValue::Int(7)), Ok(7));
+        assert_eq!(
+            u64::from_value(DynValue::Int(-1)),
+            Err(ConversionError::new("integer out of range"))
+        );
+        assert_eq!(
+            i32::from_value(DynValue::Int(i64::MAX)),
+            Err(ConversionError::new("integer out of range"))
+        );
+        assert_eq!(
+            i32::from_value(DynValue::Float(1.0)),
+            Err(ConversionError::new("expected an integer"))
+        );
+    }
+
+    #[test]
+    fn field_path() {
+        let error = ConversionError::new("expected a string")
+            .with_tag(3)
+            .with_tag(1);
+        assert_eq!(error.tag_path(), &[3, 1]);
+        assert_eq!(
+            error.to_string(),
+            "failed to convert dynamic message: field 3: field 1: expected a string"
+        );
+    }
+}
@@ -4,19 +4,26 @@
 
 #![allow(clippy::implicit_hasher, clippy::ptr_arg)]
 
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::String;
+#[cfg(feature = "decode-metrics")]
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::cmp::min;
 use core::mem;
 use core::str;
+#[cfg(feature = "decode-metrics")]
+use core::sync::atomic::{AtomicUsize, Ordering};
 use core::u32;
 use core::usize;
 
 use ::bytes::{Buf, BufMut, Bytes};
 
 use crate::DecodeError;
+use crate::LazyStr;
 use crate::Message;
 
 /// Encodes an integer value into LEB128 variable length format, and writes it to the buffer.
@@ -53,8 +60,16 @@ where
     let byte = bytes[0];
     if byte < 0x80 {
         buf.advance(1);
-        Ok(u64::from(byte))
-    } else if len > 10 || bytes[len - 1] < 0x80 {
+        return Ok(u64::from(byte));
+    }
+
+    #[cfg(feature = "fast-varint-decode")]
+    if let Some((value, advance)) = decode_varint_word(bytes) {
+        buf.advance(advance);
+        return Ok(value);
+    }
+
+    if len > 10 || bytes[len - 1] < 0x80 {
         let (value, advance) = decode_varint_slice(bytes)?;
         buf.advance(advance);
         Ok(value)
@@ -63,6 +78,30 @@ where
     }
 }
 
+/// Decodes a LEB128-encoded variable length integer of at most 8 bytes from the first 8 bytes of
+/// the slice at once, returning the value and the number of bytes read.
+///
+/// Returns `None` if fewer than 8 bytes are available, or if the varint is longer than 8 bytes.
+#[cfg(feature = "fast-varint-decode")]
+#[inline]
+fn decode_varint_word(bytes: &[u8]) -> Option<(u64, usize)> {
+    let word = u64::from_le_bytes(bytes.get(..8)?.try_into().unwrap());
+    // The high bit of each byte which ends the varint.
+    let ends = !word & 0x8080_8080_8080_8080;
+    if ends == 0 {
+        return None;
+    }
+    let len = ends.trailing_zeros() as usize / 8 + 1;
+
+    // Keep the low 7 bits of each byte up to the end of the varint, then pack them together,
+    // doubling the width of the groups at each step.
+    let value = word & (ends ^ (ends - 1)) & 0x7f7f_7f7f_7f7f_7f7f;
+    let value = ((value & 0x7f00_7f00_7f00_7f00) >> 1) | (value & 0x007f_007f_007f_007f);
+    let value = ((value & 0x3fff_0000_3fff_0000) >> 2) | (value & 0x0000_3fff_0000_3fff);
+    let value = ((value & 0x0fff_ffff_0000_0000) >> 4) | (value & 0x0000_0000_0fff_ffff);
+    Some((value, len))
+}
+
 /// Decodes a LEB128-encoded variable length integer from the slice, returning the value and the
 /// number of bytes read.
 ///
@@ -200,6 +239,21 @@ pub struct DecodeContext {
     /// crate with the `no-recursion-limit` feature.
     #[cfg(not(feature = "no-recursion-limit"))]
     recurse_count: u32,
+
+    /// How many more bytes the collections being decoded into may request, shared by every
+    /// context of a single decode.
+    ///
+    /// There is no limit unless the context was created with `with_allocation_limit`, which
+    /// requires the `decode-metrics` feature.
+    #[cfg(feature = "decode-metrics")]
+    allocation_budget: Option<Arc<AtomicUsize>>,
+
+    /// The longest string or bytes value which may be decoded, if limited.
+    ///
+    /// There is no limit unless the context was created with `with_max_value_len`, which requires
+    /// the `decode-metrics` feature.
+    #[cfg(feature = "decode-metrics")]
+    max_value_len: Option<usize>,
 }
 
 #[cfg(not(feature = "no-recursion-limit"))]
@@ -208,11 +262,39 @@ impl Default for DecodeContext {
     fn default() -> DecodeContext {
         DecodeContext {
             recurse_count: crate::RECURSION_LIMIT,
+            #[cfg(feature = "decode-metrics")]
+            allocation_budget: None,
+            #[cfg(feature = "decode-metrics")]
+            max_value_len: None,
         }
     }
 }
 
 impl DecodeContext {
+    /// Creates a context which fails decoding once the collections being decoded into have
+    /// requested more than `limit` bytes of memory in total.
+    #[cfg(feature = "decode-metrics")]
+    pub fn with_allocation_limit(limit: usize) -> DecodeContext {
+        DecodeContext {
+            #[cfg(not(feature = "no-recursion-limit"))]
+            recurse_count: crate::RECURSION_LIMIT,
+            allocation_budget: Some(Arc::new(AtomicUsize::new(limit))),
+            max_value_len: None,
+        }
+    }
+
+    /// Creates a context which fails decoding when any string or bytes value is longer than
+    /// `len` bytes, before the value is copied.
+    #[cfg(feature = "decode-metrics")]
+    pub fn with_max_value_len(len: usize) -> DecodeContext {
+        DecodeContext {
+            #[cfg(not(feature = "no-recursion-limit"))]
+            recurse_count: crate::RECURSION_LIMIT,
+            allocation_budget: None,
+            max_value_len: Some(len),
+        }
+    }
+
     /// Call this function before recursively decoding.
     ///
     /// There is no `exit` function since this function creates a new `DecodeContext`
@@ -223,13 +305,67 @@ impl DecodeContext {
     pub(crate) fn enter_recursion(&self) -> DecodeContext {
         DecodeContext {
             recurse_count: self.recurse_count - 1,
+            #[cfg(feature = "decode-metrics")]
+            allocation_budget: self.allocation_budget.clone(),
+            #[cfg(feature = "decode-metrics")]
+            max_value_len: self.max_value_len,
         }
     }
 
     #[cfg(feature = "no-recursion-limit")]
     #[inline]
     pub(crate) fn enter_recursion(&self) -> DecodeContext {
-        DecodeContext {}
+        DecodeContext {
+            #[cfg(feature = "decode-metrics")]
+            allocation_budget: self.allocation_budget.clone(),
+            #[cfg(feature = "decode-metrics")]
+            max_value_len: self.max_value_len,
+        }
+    }
+
+    /// Records that `len` bytes of memory are being requested for decoded values.
+    ///
+    /// Returns `Err<DecodeError>` if this exceeds the allocation limit.
+    #[cfg(feature = "decode-metrics")]
+    #[inline]
+    pub(crate) fn allocate(&self, len: usize) -> Result<(), DecodeError> {
+        if let Some(budget) = &self.allocation_budget {
+            budget
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                    remaining.checked_sub(len)
+                })
+                .map_err(|_| DecodeError::new("allocation limit exceeded"))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "decode-metrics"))]
+    #[inline]
+    #[allow(clippy::unnecessary_wraps)] // needed in other features
+    pub(crate) fn allocate(&self, _len: usize) -> Result<(), DecodeError> {
+        Ok(())
+    }
+
+    /// Checks that a string or bytes value of `len` bytes is within the maximum value length.
+    ///
+    /// Returns `Err<DecodeError>` if the value is too long.
+    #[cfg(feature = "decode-metrics")]
+    #[inline]
+    pub(crate) fn check_value_len(&self, len: usize) -> Result<(), DecodeError> {
+        match self.max_value_len {
+            Some(max) if len > max => Err(DecodeError::new(format!(
+                "value of {} bytes exceeds the maximum length of {} bytes",
+                len, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(not(feature = "decode-metrics"))]
+    #[inline]
+    #[allow(clippy::unnecessary_wraps)] // needed in other features
+    pub(crate) fn check_value_len(&self, _len: usize) -> Result<(), DecodeError> {
+        Ok(())
     }
 
     /// Checks whether the recursion limit has been reached in the stack of
@@ -264,6 +400,8 @@ pub fn encoded_len_varint(value: u64) -> usize {
     ((((value | 1).leading_zeros() ^ 63) * 9 + 73) / 64) as usize
 }
 
+/// The wire type of an encoded field, which tells a decoder how to find the end of the field's
+/// value without knowing its type.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[repr(u8)]
 pub enum WireType {
@@ -331,6 +469,29 @@ where
     Ok((tag, wire_type))
 }
 
+/// Decodes the Protobuf field key at the front of the buffer without consuming it.
+///
+/// Returns `None` if the buffer is empty. This allows custom decoders to look ahead at the next
+/// field before deciding how to proceed. The key must be contiguous in the buffer's current
+/// chunk; a key which is split across chunks is reported as an error.
+pub fn peek_key<B>(buf: &B) -> Option<Result<(u32, WireType), DecodeError>>
+where
+    B: Buf,
+{
+    if !buf.has_remaining() {
+        return None;
+    }
+
+    let mut chunk = buf.chunk();
+    let terminated = chunk.iter().take(10).any(|&byte| byte < 0x80);
+    if !terminated && chunk.len() < min(10, buf.remaining()) {
+        return Some(Err(DecodeError::new(
+            "field key is split across buffer chunks",
+        )));
+    }
+    Some(decode_key(&mut chunk))
+}
+
 /// Returns the width of an encoded Protobuf field key with the given tag.
 /// The returned width will be between 1 and 5 bytes (inclusive).
 #[inline]
@@ -338,6 +499,76 @@ pub fn key_len(tag: u32) -> usize {
     encoded_len_varint(u64::from(tag << 3))
 }
 
+/// The initial value of a schema fingerprint, before any fields are folded into it.
+///
+/// Schema fingerprints are a 64-bit FNV-1a hash over the `(tag, wire type)` pairs of a message's
+/// fields, in tag order.
+pub const FINGERPRINT_SEED: u64 = 0xcbf2_9ce4_8422_2325;
+
+const fn fingerprint_bytes(mut fingerprint: u64, bytes: &[u8]) -> u64 {
+    let mut i = 0;
+    while i < bytes.len() {
+        fingerprint ^= bytes[i] as u64;
+        fingerprint = fingerprint.wrapping_mul(0x0000_0100_0000_01b3);
+        i += 1;
+    }
+    fingerprint
+}
+
+/// Folds a field's tag and wire type into a schema fingerprint.
+pub const fn fingerprint_field(fingerprint: u64, tag: u32, wire_type: WireType) -> u64 {
+    let fingerprint = fingerprint_bytes(fingerprint, &tag.to_le_bytes());
+    fingerprint_bytes(fingerprint, &[wire_type as u8])
+}
+
+/// Folds the schema fingerprint of a nested oneof into a schema fingerprint.
+pub const fn fingerprint_nested(fingerprint: u64, nested: u64) -> u64 {
+    fingerprint_bytes(fingerprint, &nested.to_le_bytes())
+}
+
+/// The bytes which begin a framed message.
+pub const FRAME_MAGIC: [u8; 4] = *b"PRST";
+
+/// Encodes `msg` in a self-describing frame: [`FRAME_MAGIC`], the message's schema fingerprint as
+/// a little-endian `u64`, then the length-delimited message.
+pub fn encode_framed<M>(fingerprint: u64, msg: &M) -> Vec<u8>
+where
+    M: Message,
+{
+    let len = msg.encoded_len();
+    let mut buf = Vec::with_capacity(FRAME_MAGIC.len() + 8 + encoded_len_varint(len as u64) + len);
+    buf.put_slice(&FRAME_MAGIC);
+    buf.put_u64_le(fingerprint);
+    encode_varint(len as u64, &mut buf);
+    msg.encode_raw(&mut buf);
+    buf
+}
+
+/// Decodes a message encoded by [`encode_framed`], checking that it was encoded with the schema
+/// fingerprint `fingerprint`.
+pub fn decode_framed<M, B>(fingerprint: u64, mut buf: B) -> Result<M, DecodeError>
+where
+    M: Message + Default,
+    B: Buf,
+{
+    if buf.remaining() < FRAME_MAGIC.len() + 8 {
+        return Err(DecodeError::new("buffer underflow"));
+    }
+    let mut magic = [0; 4];
+    buf.copy_to_slice(&mut magic);
+    if magic != FRAME_MAGIC {
+        return Err(DecodeError::new("invalid frame magic"));
+    }
+    let encoded_fingerprint = buf.get_u64_le();
+    if encoded_fingerprint != fingerprint {
+        return Err(DecodeError::new(format!(
+            "schema fingerprint mismatch: {:#018x} (expected {:#018x})",
+            encoded_fingerprint, fingerprint
+        )));
+    }
+    M::decode_length_delimited(buf)
+}
+
 /// Checks that the expected wire type matches the actual wire type,
 /// or returns an error result.
 #[inline]
@@ -386,10 +617,27 @@ pub fn skip_field<B>(
     buf: &mut B,
     ctx: DecodeContext,
 ) -> Result<(), DecodeError>
+where
+    B: Buf,
+{
+    skip_field_at_depth(wire_type, tag, buf, ctx, 0)
+}
+
+/// Skips a field which is nested `depth` groups deep within the field being skipped.
+fn skip_field_at_depth<B>(
+    wire_type: WireType,
+    tag: u32,
+    buf: &mut B,
+    ctx: DecodeContext,
+    depth: u32,
+) -> Result<(), DecodeError>
 where
     B: Buf,
 {
     ctx.limit_reached()?;
+    if depth >= crate::SKIP_DEPTH_LIMIT {
+        return Err(DecodeError::new("skipped groups are nested too deeply"));
+    }
     let len = match wire_type {
         WireType::Varint => decode_varint(buf).map(|_| 0)?,
         WireType::ThirtyTwoBit => 4,
@@ -404,7 +652,13 @@ where
                     }
                     break 0;
                 }
-                _ => skip_field(inner_wire_type, inner_tag, buf, ctx.enter_recursion())?,
+                _ => skip_field_at_depth(
+                    inner_wire_type,
+                    inner_tag,
+                    buf,
+                    ctx.enter_recursion(),
+                    depth + 1,
+                )?,
             }
         },
         WireType::EndGroup => return Err(DecodeError::new("unexpected end group tag")),
@@ -418,6 +672,61 @@ where
     Ok(())
 }
 
+/// Helper function which decodes each value of the repeated numeric field `tag` of the message in
+/// `buf`, passing the values to `f` one at a time. Values of the field may be packed or unpacked,
+/// and other fields are skipped.
+fn for_each_element<T, B, M, F>(
+    tag: u32,
+    element_wire_type: WireType,
+    mut buf: B,
+    mut merge: M,
+    mut f: F,
+) -> Result<(), DecodeError>
+where
+    T: Default,
+    B: Buf,
+    M: FnMut(WireType, &mut T, &mut B, DecodeContext) -> Result<(), DecodeError>,
+    F: FnMut(T) -> Result<(), DecodeError>,
+{
+    let ctx = DecodeContext::default();
+    while buf.has_remaining() {
+        let (field_tag, wire_type) = decode_key(&mut buf)?;
+        if field_tag != tag {
+            skip_field(wire_type, field_tag, &mut buf, ctx.clone())?;
+        } else if wire_type == WireType::LengthDelimited {
+            // Packed.
+            merge_loop(&mut f, &mut buf, ctx.clone(), |f, buf, ctx| {
+                let mut value = T::default();
+                merge(element_wire_type, &mut value, buf, ctx)?;
+                f(value)
+            })?;
+        } else {
+            // Unpacked.
+            check_wire_type(element_wire_type, wire_type)?;
+            let mut value = T::default();
+            merge(wire_type, &mut value, &mut buf, ctx.clone())?;
+            f(value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Helper macro which emits a `for_each_repeated` function for the numeric type.
+macro_rules! for_each_repeated {
+    ($ty:ty, $wire_type:expr) => {
+        /// Decodes each value of the repeated field `tag` of the message encoded in `buf`,
+        /// passing the values to `f` one at a time rather than collecting them. Other fields are
+        /// skipped, and decoding stops at the first error returned by `f`.
+        pub fn for_each_repeated<B, F>(tag: u32, buf: B, f: F) -> Result<(), DecodeError>
+        where
+            B: Buf,
+            F: FnMut($ty) -> Result<(), DecodeError>,
+        {
+            for_each_element(tag, $wire_type, buf, merge, f)
+        }
+    };
+}
+
 /// Helper macro which emits an `encode_repeated` function for the type.
 macro_rules! encode_repeated {
     ($ty:ty) => {
@@ -450,6 +759,7 @@ macro_rules! merge_repeated_numeric {
             if wire_type == WireType::LengthDelimited {
                 // Packed.
                 merge_loop(values, buf, ctx, |values, buf, ctx| {
+                    ctx.allocate(mem::size_of::<$ty>())?;
                     let mut value = Default::default();
                     $merge($wire_type, &mut value, buf, ctx)?;
                     values.push(value);
@@ -458,6 +768,7 @@ macro_rules! merge_repeated_numeric {
             } else {
                 // Unpacked.
                 check_wire_type($wire_type, wire_type)?;
+                ctx.allocate(mem::size_of::<$ty>())?;
                 let mut value = Default::default();
                 $merge(wire_type, &mut value, buf, ctx)?;
                 values.push(value);
@@ -514,7 +825,33 @@ macro_rules! varint {
                 }
             }
 
+            /// Encodes the values yielded by an iterator as a packed field, without collecting
+            /// them. The iterator is traversed twice: once by a clone of it to compute the length
+            /// of the field, and once to write the values.
+            pub fn encode_packed_iter<I, B>(tag: u32, values: I, buf: &mut B)
+            where
+                I: IntoIterator<Item = $ty>,
+                I::IntoIter: Clone,
+                B: BufMut,
+            {
+                let values = values.into_iter();
+                let len: usize = values.clone().map(|value| {
+                    let $to_uint64_value = &value;
+                    encoded_len_varint($to_uint64)
+                }).sum();
+                if len == 0 { return; }
+
+                encode_key(tag, WireType::LengthDelimited, buf);
+                encode_varint(len as u64, buf);
+
+                for value in values {
+                    let $to_uint64_value = &value;
+                    encode_varint($to_uint64, buf);
+                }
+            }
+
             merge_repeated_numeric!($ty, WireType::Varint, merge, merge_repeated);
+            for_each_repeated!($ty, WireType::Varint);
 
             #[inline]
             pub fn encoded_len(tag: u32, $to_uint64_value: &$ty) -> usize {
@@ -568,6 +905,14 @@ macro_rules! varint {
                                    encode_packed, merge_repeated,
                                    encoded_len_packed)?;
                     }
+                    #[test]
+                    fn check_packed_iter(value: Vec<$ty>, tag in MIN_TAG..=MAX_TAG) {
+                        let mut expected = Vec::new();
+                        encode_packed(tag, &value, &mut expected);
+                        let mut encoded = Vec::new();
+                        encode_packed_iter(tag, value.iter().copied(), &mut encoded);
+                        prop_assert_eq!(encoded, expected);
+                    }
                 }
             }
          }
@@ -648,12 +993,92 @@ macro_rules! fixed_width {
                 let len = values.len() as u64 * $width;
                 encode_varint(len as u64, buf);
 
+                // The in-memory representation is the wire representation on little-endian
+                // targets, so the values can be copied in bulk.
+                #[cfg(all(feature = "bytemuck", target_endian = "little"))]
+                buf.put_slice(bytemuck::cast_slice(values));
+
+                #[cfg(not(all(feature = "bytemuck", target_endian = "little")))]
                 for value in values {
                     buf.$put(*value);
                 }
             }
 
-            merge_repeated_numeric!($ty, $wire_type, merge, merge_repeated);
+            /// Encodes the values yielded by an iterator as a packed field, without collecting
+            /// them. The iterator is traversed twice: once by a clone of it to count the values,
+            /// and once to write them.
+            pub fn encode_packed_iter<I, B>(tag: u32, values: I, buf: &mut B)
+            where
+                I: IntoIterator<Item = $ty>,
+                I::IntoIter: Clone,
+                B: BufMut,
+            {
+                let values = values.into_iter();
+                let len = values.clone().count() as u64 * $width;
+                if len == 0 {
+                    return;
+                }
+
+                encode_key(tag, WireType::LengthDelimited, buf);
+                encode_varint(len, buf);
+                for value in values {
+                    buf.$put(value);
+                }
+            }
+
+            pub fn merge_repeated<B>(
+                wire_type: WireType,
+                values: &mut Vec<$ty>,
+                buf: &mut B,
+                ctx: DecodeContext,
+            ) -> Result<(), DecodeError>
+            where
+                B: Buf,
+            {
+                if wire_type == WireType::LengthDelimited {
+                    // Packed. Every element has the same width, so the element count is known
+                    // up front and the vector only needs to be grown once.
+                    let len = decode_varint(buf)?;
+                    if len > buf.remaining() as u64 {
+                        return Err(DecodeError::new("buffer underflow"));
+                    }
+                    let len = len as usize;
+                    // Reject a truncated final element before decoding any of the others.
+                    if len % $width != 0 {
+                        return Err(DecodeError::new(format!(
+                            "packed field length {} is not a multiple of the element size {}",
+                            len, $width
+                        )));
+                    }
+
+                    let count = len / $width;
+                    ctx.allocate(count * mem::size_of::<$ty>())?;
+                    values.reserve_exact(count);
+
+                    #[cfg(all(feature = "bytemuck", target_endian = "little"))]
+                    {
+                        let start = values.len();
+                        values.resize(start + count, Default::default());
+                        buf.copy_to_slice(bytemuck::cast_slice_mut(&mut values[start..]));
+                    }
+
+                    #[cfg(not(all(feature = "bytemuck", target_endian = "little")))]
+                    for _ in 0..count {
+                        values.push(buf.$get());
+                    }
+                    Ok(())
+                } else {
+                    // Unpacked.
+                    check_wire_type($wire_type, wire_type)?;
+                    ctx.allocate(mem::size_of::<$ty>())?;
+                    let mut value = Default::default();
+                    merge(wire_type, &mut value, buf, ctx)?;
+                    values.push(value);
+                    Ok(())
+                }
+            }
+
+            for_each_repeated!($ty, $wire_type);
 
             #[inline]
             pub fn encoded_len(tag: u32, _: &$ty) -> usize {
@@ -700,6 +1125,61 @@ macro_rules! fixed_width {
                                    encode_packed, merge_repeated,
                                    encoded_len_packed)?;
                     }
+                    #[test]
+                    fn check_packed_iter(value: Vec<$ty>, tag in MIN_TAG..=MAX_TAG) {
+                        let mut expected = Vec::new();
+                        encode_packed(tag, &value, &mut expected);
+                        let mut encoded = Vec::new();
+                        encode_packed_iter(tag, value.iter().copied(), &mut encoded);
+                        prop_assert_eq!(encoded, expected);
+                    }
+                }
+
+                #[test]
+                fn merge_packed_reserves_exact() {
+                    let values: Vec<$ty> = (0..10_000).map(|i| i as $ty).collect();
+                    let mut buf = Vec::new();
+                    encode_packed(1, &values, &mut buf);
+
+                    let mut buf = buf.as_slice();
+                    let (_, wire_type) = decode_key(&mut buf).unwrap();
+                    let mut decoded = Vec::new();
+                    merge_repeated(wire_type, &mut decoded, &mut buf, DecodeContext::default())
+                        .unwrap();
+                    assert_eq!(decoded, values);
+                    assert_eq!(decoded.capacity(), values.len());
+                }
+
+                #[test]
+                fn encode_packed_matches_elementwise() {
+                    let values: Vec<$ty> = (0..100).map(|i| (i as $ty) * (7 as $ty)).collect();
+                    let mut encoded = Vec::new();
+                    encode_packed(1, &values, &mut encoded);
+
+                    let mut expected = Vec::new();
+                    encode_key(1, WireType::LengthDelimited, &mut expected);
+                    encode_varint(values.len() as u64 * $width, &mut expected);
+                    for value in &values {
+                        expected.$put(*value);
+                    }
+                    assert_eq!(encoded, expected);
+                }
+
+                #[test]
+                fn merge_packed_rejects_partial_element() {
+                    // A packed length which is not a multiple of the element width.
+                    let mut buf = Vec::new();
+                    encode_varint($width + 1, &mut buf);
+                    buf.resize(buf.len() + $width * 2, 0);
+
+                    let mut decoded = Vec::<$ty>::new();
+                    assert!(merge_repeated(
+                        WireType::LengthDelimited,
+                        &mut decoded,
+                        &mut buf.as_slice(),
+                        DecodeContext::default(),
+                    )
+                    .is_err());
                 }
             }
         }
@@ -757,6 +1237,9 @@ fixed_width!(
 /// Macro which emits encoding functions for a length-delimited type.
 macro_rules! length_delimited {
     ($ty:ty) => {
+        length_delimited!($ty, Default::default());
+    };
+    ($ty:ty, $empty:expr) => {
         encode_repeated!($ty);
 
         pub fn merge_repeated<B>(
@@ -769,7 +1252,8 @@ macro_rules! length_delimited {
             B: Buf,
         {
             check_wire_type(WireType::LengthDelimited, wire_type)?;
-            let mut value = Default::default();
+            let mut value = $empty;
+            ctx.allocate(mem::size_of_val(&value))?;
             merge(wire_type, &mut value, buf, ctx)?;
             values.push(value);
             Ok(())
@@ -794,15 +1278,30 @@ macro_rules! length_delimited {
 pub mod string {
     use super::*;
 
-    pub fn encode<B>(tag: u32, value: &String, buf: &mut B)
+    pub fn encode<A, B>(tag: u32, value: &A, buf: &mut B)
     where
+        A: StringAdapter,
         B: BufMut,
     {
         encode_key(tag, WireType::LengthDelimited, buf);
         encode_varint(value.len() as u64, buf);
-        buf.put_slice(value.as_bytes());
+        buf.put_slice(value.as_str().as_bytes());
+    }
+
+    pub fn merge<A, B>(
+        wire_type: WireType,
+        value: &mut A,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError>
+    where
+        A: StringAdapter,
+        B: Buf,
+    {
+        value.merge_from(wire_type, buf, ctx)
     }
-    pub fn merge<B>(
+
+    pub(super) fn merge_string<B>(
         wire_type: WireType,
         value: &mut String,
         buf: &mut B,
@@ -848,10 +1347,20 @@ pub mod string {
         }
     }
 
-    length_delimited!(String);
+    length_delimited!(impl StringAdapter);
+
+    /// Resets the value to the empty string.
+    pub fn clear<A>(value: &mut A)
+    where
+        A: StringAdapter,
+    {
+        value.clear()
+    }
 
     #[cfg(test)]
     mod test {
+        use alloc::boxed::Box;
+
         use proptest::prelude::*;
 
         use super::super::test::{check_collection_type, check_type};
@@ -860,8 +1369,15 @@ pub mod string {
         proptest! {
             #[test]
             fn check(value: String, tag in MIN_TAG..=MAX_TAG) {
-                super::test::check_type(value, tag, WireType::LengthDelimited,
-                                        encode, merge, encoded_len)?;
+                super::test::check_type::<String, String>(value, tag, WireType::LengthDelimited,
+                                                          encode, merge, encoded_len)?;
+            }
+
+            #[test]
+            fn check_box_str(value: String, tag in MIN_TAG..=MAX_TAG) {
+                let value = value.into_boxed_str();
+                super::test::check_type::<Box<str>, Box<str>>(value, tag, WireType::LengthDelimited,
+                                                              encode, merge, encoded_len)?;
             }
             #[test]
             fn check_repeated(value: Vec<String>, tag in MIN_TAG..=MAX_TAG) {
@@ -873,16 +1389,52 @@ pub mod string {
     }
 }
 
+pub trait StringAdapter: sealed::StringAdapter {}
+
 pub trait BytesAdapter: sealed::BytesAdapter {}
 
+/// A collection which can hold the values of a repeated field.
+pub trait RepeatedAdapter<T>: sealed::RepeatedAdapter<T> {}
+
 mod sealed {
-    use super::{Buf, BufMut};
+    use alloc::vec::Vec;
+
+    use super::{Buf, BufMut, DecodeContext, DecodeError, WireType};
+
+    pub trait StringAdapter: Default + Sized + 'static {
+        fn as_str(&self) -> &str;
+
+        fn len(&self) -> usize {
+            self.as_str().len()
+        }
+
+        /// Replaces the contents of this string with a string decoded from the buffer, which
+        /// must be well-formed UTF-8.
+        ///
+        /// If an error is returned, the string is left empty.
+        fn merge_from<B>(
+            &mut self,
+            wire_type: WireType,
+            buf: &mut B,
+            ctx: DecodeContext,
+        ) -> Result<(), DecodeError>
+        where
+            B: Buf;
+
+        /// Resets this string to the empty string.
+        fn clear(&mut self);
+    }
+
+    pub trait BytesAdapter: Sized + 'static {
+        /// Returns a buffer in its empty state.
+        fn empty() -> Self;
 
-    pub trait BytesAdapter: Default + Sized + 'static {
         fn len(&self) -> usize;
 
         /// Replace contents of this buffer with the contents of another buffer.
-        fn replace_with<B>(&mut self, buf: B)
+        ///
+        /// An error is returned if this buffer cannot hold the contents.
+        fn replace_with<B>(&mut self, buf: B) -> Result<(), DecodeError>
         where
             B: Buf;
 
@@ -891,24 +1443,93 @@ mod sealed {
         where
             B: BufMut;
 
+        /// Resets this buffer to its empty state, keeping any allocated capacity.
+        fn clear(&mut self);
+
+        /// Returns `true` if this buffer is in its empty state, which need not be encoded.
         fn is_empty(&self) -> bool {
             self.len() == 0
         }
     }
+
+    pub trait RepeatedAdapter<T>: Default {
+        /// Returns the values as a vector which decoded values can be appended to.
+        fn as_mut_vec(&mut self) -> &mut Vec<T>;
+
+        /// Removes all values.
+        fn clear(&mut self);
+    }
+}
+
+impl StringAdapter for String {}
+
+impl sealed::StringAdapter for String {
+    fn as_str(&self) -> &str {
+        self
+    }
+
+    fn merge_from<B>(
+        &mut self,
+        wire_type: WireType,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError>
+    where
+        B: Buf,
+    {
+        string::merge_string(wire_type, self, buf, ctx)
+    }
+
+    fn clear(&mut self) {
+        String::clear(self)
+    }
+}
+
+/// A compact, immutable string.
+impl StringAdapter for Box<str> {}
+
+impl sealed::StringAdapter for Box<str> {
+    fn as_str(&self) -> &str {
+        self
+    }
+
+    fn merge_from<B>(
+        &mut self,
+        wire_type: WireType,
+        buf: &mut B,
+        ctx: DecodeContext,
+    ) -> Result<(), DecodeError>
+    where
+        B: Buf,
+    {
+        let mut value = mem::take(self).into_string();
+        let result = string::merge_string(wire_type, &mut value, buf, ctx);
+        *self = value.into_boxed_str();
+        result
+    }
+
+    fn clear(&mut self) {
+        *self = Box::default();
+    }
 }
 
 impl BytesAdapter for Bytes {}
 
 impl sealed::BytesAdapter for Bytes {
+    fn empty() -> Self {
+        Default::default()
+    }
+
     fn len(&self) -> usize {
         Buf::remaining(self)
     }
 
-    fn replace_with<B>(&mut self, mut buf: B)
+    fn replace_with<B>(&mut self, mut buf: B) -> Result<(), DecodeError>
     where
         B: Buf,
     {
         *self = buf.copy_to_bytes(buf.remaining());
+        Ok(())
     }
 
     fn append_to<B>(&self, buf: &mut B)
@@ -917,22 +1538,31 @@ impl sealed::BytesAdapter for Bytes {
     {
         buf.put(self.clone())
     }
+
+    fn clear(&mut self) {
+        Bytes::clear(self)
+    }
 }
 
 impl BytesAdapter for Vec<u8> {}
 
 impl sealed::BytesAdapter for Vec<u8> {
+    fn empty() -> Self {
+        Default::default()
+    }
+
     fn len(&self) -> usize {
         Vec::len(self)
     }
 
-    fn replace_with<B>(&mut self, buf: B)
+    fn replace_with<B>(&mut self, buf: B) -> Result<(), DecodeError>
     where
         B: Buf,
     {
         self.clear();
         self.reserve(buf.remaining());
         self.put(buf);
+        Ok(())
     }
 
     fn append_to<B>(&self, buf: &mut B)
@@ -941,6 +1571,258 @@ impl sealed::BytesAdapter for Vec<u8> {
     {
         buf.put(self.as_slice())
     }
+
+    fn clear(&mut self) {
+        Vec::clear(self)
+    }
+}
+
+impl BytesAdapter for LazyStr {}
+
+impl sealed::BytesAdapter for LazyStr {
+    fn empty() -> Self {
+        Default::default()
+    }
+
+    fn len(&self) -> usize {
+        LazyStr::len(self)
+    }
+
+    fn replace_with<B>(&mut self, mut buf: B) -> Result<(), DecodeError>
+    where
+        B: Buf,
+    {
+        *self = LazyStr::from_bytes(buf.copy_to_bytes(buf.remaining()));
+        Ok(())
+    }
+
+    fn append_to<B>(&self, buf: &mut B)
+    where
+        B: BufMut,
+    {
+        buf.put(self.as_bytes())
+    }
+
+    fn clear(&mut self) {
+        LazyStr::clear(self)
+    }
+}
+
+#[cfg(feature = "arrayvec")]
+impl<const N: usize> BytesAdapter for arrayvec::ArrayVec<u8, N> {}
+
+#[cfg(feature = "arrayvec")]
+impl<const N: usize> sealed::BytesAdapter for arrayvec::ArrayVec<u8, N> {
+    fn empty() -> Self {
+        arrayvec::ArrayVec::new()
+    }
+
+    fn len(&self) -> usize {
+        arrayvec::ArrayVec::len(self)
+    }
+
+    fn replace_with<B>(&mut self, mut buf: B) -> Result<(), DecodeError>
+    where
+        B: Buf,
+    {
+        self.clear();
+        while buf.has_remaining() {
+            let chunk = buf.chunk();
+            let len = chunk.len();
+            self.try_extend_from_slice(chunk)
+                .map_err(|_| DecodeError::new("bytes value exceeds capacity"))?;
+            buf.advance(len);
+        }
+        Ok(())
+    }
+
+    fn append_to<B>(&self, buf: &mut B)
+    where
+        B: BufMut,
+    {
+        buf.put(self.as_slice())
+    }
+
+    fn clear(&mut self) {
+        arrayvec::ArrayVec::clear(self)
+    }
+}
+
+/// A fixed-length byte array, such as a hash. Decoding a value of any other length fails, and the
+/// all-zero array is the empty state.
+impl<const N: usize> BytesAdapter for [u8; N] {}
+
+impl<const N: usize> sealed::BytesAdapter for [u8; N] {
+    fn empty() -> Self {
+        [0; N]
+    }
+
+    fn len(&self) -> usize {
+        N
+    }
+
+    fn replace_with<B>(&mut self, mut buf: B) -> Result<(), DecodeError>
+    where
+        B: Buf,
+    {
+        if buf.remaining() != N {
+            return Err(DecodeError::new(format!(
+                "invalid length for fixed-size bytes value: expected {}, found {}",
+                N,
+                buf.remaining()
+            )));
+        }
+        buf.copy_to_slice(self);
+        Ok(())
+    }
+
+    fn append_to<B>(&self, buf: &mut B)
+    where
+        B: BufMut,
+    {
+        buf.put_slice(self)
+    }
+
+    fn clear(&mut self) {
+        self.fill(0)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.iter().all(|&byte| byte == 0)
+    }
+}
+
+/// An OS string, as its raw bytes. Only Unix-like platforms are supported, since on other platforms
+/// OS strings are not byte strings.
+#[cfg(all(feature = "std", unix))]
+impl BytesAdapter for std::ffi::OsString {}
+
+#[cfg(all(feature = "std", unix))]
+impl sealed::BytesAdapter for std::ffi::OsString {
+    fn empty() -> Self {
+        Default::default()
+    }
+
+    fn len(&self) -> usize {
+        std::ffi::OsStr::len(self)
+    }
+
+    fn replace_with<B>(&mut self, buf: B) -> Result<(), DecodeError>
+    where
+        B: Buf,
+    {
+        use std::os::unix::ffi::OsStringExt;
+
+        // Reuse the string's allocation.
+        let mut bytes = mem::take(self).into_vec();
+        bytes.clear();
+        bytes.reserve(buf.remaining());
+        bytes.put(buf);
+        *self = std::ffi::OsString::from_vec(bytes);
+        Ok(())
+    }
+
+    fn append_to<B>(&self, buf: &mut B)
+    where
+        B: BufMut,
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        buf.put_slice(self.as_bytes())
+    }
+
+    fn clear(&mut self) {
+        std::ffi::OsString::clear(self)
+    }
+}
+
+/// A path, as the raw bytes of its OS string. Like `OsString`, this is only supported on Unix-like
+/// platforms.
+#[cfg(all(feature = "std", unix))]
+impl BytesAdapter for std::path::PathBuf {}
+
+#[cfg(all(feature = "std", unix))]
+impl sealed::BytesAdapter for std::path::PathBuf {
+    fn empty() -> Self {
+        Default::default()
+    }
+
+    fn len(&self) -> usize {
+        self.as_os_str().len()
+    }
+
+    fn replace_with<B>(&mut self, buf: B) -> Result<(), DecodeError>
+    where
+        B: Buf,
+    {
+        let mut os_string = mem::take(self).into_os_string();
+        sealed::BytesAdapter::replace_with(&mut os_string, buf)?;
+        *self = os_string.into();
+        Ok(())
+    }
+
+    fn append_to<B>(&self, buf: &mut B)
+    where
+        B: BufMut,
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        buf.put_slice(self.as_os_str().as_bytes())
+    }
+
+    fn clear(&mut self) {
+        self.as_mut_os_string().clear()
+    }
+}
+
+impl<T> RepeatedAdapter<T> for Vec<T> {}
+
+impl<T> sealed::RepeatedAdapter<T> for Vec<T> {
+    fn as_mut_vec(&mut self) -> &mut Vec<T> {
+        self
+    }
+
+    fn clear(&mut self) {
+        Vec::clear(self)
+    }
+}
+
+/// A slice which is either borrowed, so that it can be encoded without being copied, or owned.
+/// Decoding values into a borrowed slice first copies it into a vector.
+impl<T: Clone> RepeatedAdapter<T> for Cow<'_, [T]> {}
+
+impl<T: Clone> sealed::RepeatedAdapter<T> for Cow<'_, [T]> {
+    fn as_mut_vec(&mut self) -> &mut Vec<T> {
+        self.to_mut()
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Cow::Borrowed(_) => *self = Cow::Owned(Vec::new()),
+            Cow::Owned(values) => values.clear(),
+        }
+    }
+}
+
+pub mod repeated {
+    use super::*;
+
+    /// Returns the values of a repeated field as a vector which decoded values can be
+    /// appended to.
+    pub fn as_mut_vec<T, A>(values: &mut A) -> &mut Vec<T>
+    where
+        A: RepeatedAdapter<T>,
+    {
+        values.as_mut_vec()
+    }
+
+    /// Removes all values from a repeated field.
+    pub fn clear<T, A>(values: &mut A)
+    where
+        A: RepeatedAdapter<T>,
+    {
+        values.clear()
+    }
 }
 
 pub mod bytes {
@@ -960,7 +1842,7 @@ pub mod bytes {
         wire_type: WireType,
         value: &mut A,
         buf: &mut B,
-        _ctx: DecodeContext,
+        ctx: DecodeContext,
     ) -> Result<(), DecodeError>
     where
         A: BytesAdapter,
@@ -972,6 +1854,8 @@ pub mod bytes {
             return Err(DecodeError::new("buffer underflow"));
         }
         let len = len as usize;
+        ctx.check_value_len(len)?;
+        ctx.allocate(len)?;
 
         // Clear the existing value. This follows from the following rule in the encoding guide[1]:
         //
@@ -985,15 +1869,14 @@ pub mod bytes {
         // This is intended for A and B both being Bytes so it is zero-copy.
         // Some combinations of A and B types may cause a double-copy,
         // in which case merge_one_copy() should be used instead.
-        value.replace_with(buf.copy_to_bytes(len));
-        Ok(())
+        value.replace_with(buf.copy_to_bytes(len))
     }
 
     pub(super) fn merge_one_copy<A, B>(
         wire_type: WireType,
         value: &mut A,
         buf: &mut B,
-        _ctx: DecodeContext,
+        ctx: DecodeContext,
     ) -> Result<(), DecodeError>
     where
         A: BytesAdapter,
@@ -1005,13 +1888,39 @@ pub mod bytes {
             return Err(DecodeError::new("buffer underflow"));
         }
         let len = len as usize;
+        ctx.check_value_len(len)?;
+        ctx.allocate(len)?;
 
         // If we must copy, make sure to copy only once.
-        value.replace_with(buf.take(len));
-        Ok(())
+        value.replace_with(buf.take(len))
     }
 
-    length_delimited!(impl BytesAdapter);
+    length_delimited!(impl BytesAdapter, empty());
+
+    /// Returns a value in its empty state, the default for a bytes field.
+    pub fn empty<A>() -> A
+    where
+        A: BytesAdapter,
+    {
+        A::empty()
+    }
+
+    /// Returns `true` if the value is in its empty state, and can be omitted from a field without
+    /// presence.
+    pub fn is_empty<A>(value: &A) -> bool
+    where
+        A: BytesAdapter,
+    {
+        value.is_empty()
+    }
+
+    /// Resets the value to its empty state.
+    pub fn clear<A>(value: &mut A)
+    where
+        A: BytesAdapter,
+    {
+        value.clear()
+    }
 
     #[cfg(test)]
     mod test {
@@ -1049,6 +1958,144 @@ pub mod bytes {
                                                    encoded_len_repeated)?;
             }
         }
+
+        #[test]
+        fn merge_repeated_bytes_is_zero_copy() {
+            let values = alloc::vec![
+                Bytes::from_static(b"foo"),
+                Bytes::from_static(b"bar baz"),
+                Bytes::from(alloc::vec![0xAB; 300]),
+            ];
+            let mut encoded = Vec::new();
+            encode_repeated(7, &values, &mut encoded);
+            let source = Bytes::from(encoded);
+
+            let mut buf = source.clone();
+            let mut decoded: Vec<Bytes> = Vec::new();
+            while buf.has_remaining() {
+                let (tag, wire_type) = decode_key(&mut buf).unwrap();
+                assert_eq!(tag, 7);
+                merge_repeated(wire_type, &mut decoded, &mut buf, DecodeContext::default())
+                    .unwrap();
+            }
+            assert_eq!(decoded, values);
+
+            // Each decoded element must be a view into the source allocation, not a copy.
+            let source_range = source.as_ptr_range();
+            for value in &decoded {
+                let value_range = value.as_ptr_range();
+                assert!(source_range.start <= value_range.start);
+                assert!(value_range.end <= source_range.end);
+            }
+        }
+
+        #[cfg(feature = "arrayvec")]
+        #[test]
+        fn merge_arrayvec() {
+            use arrayvec::ArrayVec;
+
+            fn decode<const N: usize>(value: &[u8]) -> Result<ArrayVec<u8, N>, DecodeError> {
+                let mut buf = Vec::new();
+                encode(1, &value.to_vec(), &mut buf);
+                let mut buf = buf.as_slice();
+                let (_, wire_type) = decode_key(&mut buf).unwrap();
+                let mut decoded = ArrayVec::new();
+                merge(wire_type, &mut decoded, &mut buf, DecodeContext::default())?;
+                Ok(decoded)
+            }
+
+            assert_eq!(decode::<8>(b"").unwrap().as_slice(), b"");
+            assert_eq!(decode::<8>(b"exactfit").unwrap().as_slice(), b"exactfit");
+            assert!(decode::<8>(b"too large").is_err());
+
+            let value: ArrayVec<u8, 8> = decode(b"exactfit").unwrap();
+            let mut encoded = Vec::new();
+            encode(1, &value, &mut encoded);
+            assert_eq!(encoded.len(), encoded_len(1, &value));
+            assert_eq!(&encoded[2..], b"exactfit");
+        }
+
+        #[cfg(all(feature = "std", unix))]
+        #[test]
+        fn merge_os_string() {
+            use std::ffi::OsString;
+            use std::os::unix::ffi::OsStringExt;
+
+            let path = OsString::from_vec(b"/tmp/caf\xe9".to_vec());
+            assert!(path.to_str().is_none());
+
+            let mut encoded = Vec::new();
+            encode(1, &path, &mut encoded);
+            assert_eq!(encoded.len(), encoded_len(1, &path));
+            assert_eq!(&encoded[2..], b"/tmp/caf\xe9");
+
+            let mut buf = encoded.as_slice();
+            let (_, wire_type) = decode_key(&mut buf).unwrap();
+            let mut decoded = OsString::from("previous contents");
+            merge(wire_type, &mut decoded, &mut buf, DecodeContext::default()).unwrap();
+            assert_eq!(decoded, path);
+
+            assert!(!is_empty(&decoded));
+            clear(&mut decoded);
+            assert!(is_empty(&decoded));
+        }
+
+        #[cfg(all(feature = "std", unix))]
+        #[test]
+        fn merge_path_buf() {
+            use std::ffi::OsString;
+            use std::os::unix::ffi::OsStringExt;
+            use std::path::PathBuf;
+
+            let path = PathBuf::from(OsString::from_vec(b"/etc/caf\xe9.toml".to_vec()));
+
+            let mut encoded = Vec::new();
+            encode(1, &path, &mut encoded);
+            assert_eq!(encoded.len(), encoded_len(1, &path));
+            assert_eq!(&encoded[2..], b"/etc/caf\xe9.toml");
+
+            let mut buf = encoded.as_slice();
+            let (_, wire_type) = decode_key(&mut buf).unwrap();
+            let mut decoded = PathBuf::from("/previous/path");
+            merge(wire_type, &mut decoded, &mut buf, DecodeContext::default()).unwrap();
+            assert_eq!(decoded, path);
+
+            // The empty path is the empty state.
+            assert!(!is_empty(&decoded));
+            clear(&mut decoded);
+            assert!(is_empty(&decoded));
+            assert_eq!(decoded, PathBuf::new());
+        }
+
+        #[test]
+        fn merge_array() {
+            fn decode(value: &[u8]) -> Result<[u8; 32], DecodeError> {
+                let mut buf = Vec::new();
+                encode(1, &value.to_vec(), &mut buf);
+                let mut buf = buf.as_slice();
+                let (_, wire_type) = decode_key(&mut buf).unwrap();
+                let mut decoded = [0xff; 32];
+                merge(wire_type, &mut decoded, &mut buf, DecodeContext::default())?;
+                Ok(decoded)
+            }
+
+            let hash = [0xab; 32];
+            assert_eq!(decode(&hash), Ok(hash));
+            assert!(decode(&hash[..31]).is_err());
+            assert!(decode(&[0xab; 33]).is_err());
+            assert!(decode(b"").is_err());
+
+            let mut encoded = Vec::new();
+            encode(1, &hash, &mut encoded);
+            assert_eq!(encoded.len(), encoded_len(1, &hash));
+            assert_eq!(&encoded[2..], &hash);
+
+            let mut value = hash;
+            assert!(!is_empty(&value));
+            clear(&mut value);
+            assert_eq!(value, [0; 32]);
+            assert!(is_empty(&value));
+        }
     }
 }
 
@@ -1109,6 +2156,7 @@ pub mod message {
         B: Buf,
     {
         check_wire_type(WireType::LengthDelimited, wire_type)?;
+        ctx.allocate(mem::size_of::<M>())?;
         let mut msg = M::default();
         merge(WireType::LengthDelimited, &mut msg, buf, ctx)?;
         messages.push(msg);
@@ -1200,6 +2248,7 @@ pub mod group {
         B: Buf,
     {
         check_wire_type(WireType::StartGroup, wire_type)?;
+        ctx.allocate(mem::size_of::<M>())?;
         let mut msg = M::default();
         merge(tag, WireType::StartGroup, &mut msg, buf, ctx)?;
         messages.push(msg);
@@ -1355,6 +2404,7 @@ macro_rules! map {
             let mut key = Default::default();
             let mut val = val_default;
             ctx.limit_reached()?;
+            ctx.allocate(mem::size_of::<K>() + mem::size_of::<V>())?;
             merge_loop(
                 &mut (&mut key, &mut val),
                 buf,
@@ -1682,6 +2732,330 @@ mod test {
         );
     }
 
+    #[cfg(feature = "fast-varint-decode")]
+    proptest! {
+        #[test]
+        fn decode_varint_word_matches_slice(bytes in prop::collection::vec(any::<u8>(), 11..16)) {
+            match decode_varint_word(&bytes) {
+                Some(decoded) => prop_assert_eq!(decode_varint_slice(&bytes), Ok(decoded)),
+                None => prop_assert!(bytes[..8].iter().all(|&byte| byte >= 0x80)),
+            }
+        }
+
+        #[test]
+        fn decode_varint_word_roundtrip(value: u64, padding in prop::collection::vec(any::<u8>(), 0..8)) {
+            let mut encoded = Vec::new();
+            encode_varint(value, &mut encoded);
+            let len = encoded.len();
+            encoded.extend(padding);
+            let decoded = decode_varint_word(&encoded);
+            if len <= 8 && encoded.len() >= 8 {
+                prop_assert_eq!(decoded, Some((value, len)));
+            } else {
+                prop_assert_eq!(decoded, None);
+            }
+
+            let mut buf = encoded.as_slice();
+            prop_assert_eq!(decode_varint(&mut buf), Ok(value));
+            prop_assert_eq!(buf.len(), encoded.len() - len);
+        }
+    }
+
+    #[cfg(feature = "decode-metrics")]
+    #[test]
+    fn allocation_limit() {
+        let values: Vec<u64> = (0..1000).collect();
+        let mut encoded = Vec::new();
+        fixed64::encode_packed(1, &values, &mut encoded);
+
+        let merge = |limit| {
+            let mut buf = encoded.as_slice();
+            let (_, wire_type) = decode_key(&mut buf).unwrap();
+            let mut decoded = Vec::new();
+            fixed64::merge_repeated(
+                wire_type,
+                &mut decoded,
+                &mut buf,
+                DecodeContext::with_allocation_limit(limit),
+            )
+            .map(|()| decoded)
+        };
+        assert_eq!(merge(8000).unwrap(), values);
+        assert!(merge(7999).is_err());
+
+        // The limit is shared across the whole decode, not applied per field.
+        let value: Vec<u8> = alloc::vec![0; 600];
+        let mut encoded = Vec::new();
+        bytes::encode(1, &value, &mut encoded);
+        bytes::encode(1, &value, &mut encoded);
+        assert!(Vec::<u8>::decode_with_allocation_limit(&encoded[..], 1200).is_ok());
+        assert!(Vec::<u8>::decode_with_allocation_limit(&encoded[..], 1000).is_err());
+        assert!(Vec::<u8>::decode(&encoded[..]).is_ok());
+    }
+
+    #[cfg(feature = "decode-metrics")]
+    #[test]
+    fn allocation_limit_is_shared_by_nested_messages() {
+        // Each nested `BytesValue` allocates its element and its 100 bytes, well within the limit
+        // on its own, but the nested messages draw on one budget between them.
+        let values: Vec<Vec<u8>> = alloc::vec![alloc::vec![0xab; 100]; 4];
+        let mut encoded = Vec::new();
+        message::encode_repeated(1, &values, &mut encoded);
+        let per_message = mem::size_of::<Vec<u8>>() + 100;
+
+        let merge = |limit| {
+            let mut buf = encoded.as_slice();
+            let ctx = DecodeContext::with_allocation_limit(limit);
+            let mut decoded = Vec::<Vec<u8>>::new();
+            while buf.has_remaining() {
+                let (_, wire_type) = decode_key(&mut buf)?;
+                message::merge_repeated(wire_type, &mut decoded, &mut buf, ctx.clone())?;
+            }
+            Ok::<_, DecodeError>(decoded)
+        };
+        assert_eq!(merge(per_message * 4).unwrap(), values);
+        assert!(merge(per_message * 4 - 1).is_err());
+        assert!(merge(per_message * 2).is_err());
+    }
+
+    #[cfg(feature = "decode-metrics")]
+    #[test]
+    fn decode_context_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<DecodeContext>();
+        assert_send_sync::<crate::DecodeIter<&[u8], Vec<u8>>>();
+    }
+
+    #[cfg(feature = "decode-metrics")]
+    #[test]
+    fn max_value_len() {
+        let encode = |len| {
+            let mut encoded = Vec::new();
+            string::encode(1, &"x".repeat(len), &mut encoded);
+            encoded
+        };
+        assert_eq!(
+            String::decode_with_max_value_len(&encode(100)[..], 100).unwrap(),
+            "x".repeat(100)
+        );
+        assert_eq!(
+            String::decode_with_max_value_len(&encode(101)[..], 100).unwrap_err(),
+            DecodeError::new("value of 101 bytes exceeds the maximum length of 100 bytes")
+        );
+        assert!(String::decode(&encode(101)[..]).is_ok());
+
+        // The limit applies to each value, however deeply nested, rather than to their total.
+        let values: Vec<Vec<u8>> = alloc::vec![alloc::vec![0; 100]; 3];
+        let mut encoded = Vec::new();
+        message::encode_repeated(1, &values, &mut encoded);
+        let merge = |len| {
+            let mut buf = encoded.as_slice();
+            let ctx = DecodeContext::with_max_value_len(len);
+            let mut decoded = Vec::<Vec<u8>>::new();
+            while buf.has_remaining() {
+                let (_, wire_type) = decode_key(&mut buf)?;
+                message::merge_repeated(wire_type, &mut decoded, &mut buf, ctx.clone())?;
+            }
+            Ok::<_, DecodeError>(decoded)
+        };
+        assert_eq!(merge(100).unwrap(), values);
+        assert!(merge(99).is_err());
+    }
+
+    #[test]
+    fn fingerprint() {
+        // FNV-1a 64 test vectors.
+        assert_eq!(fingerprint_bytes(FINGERPRINT_SEED, b""), 0xcbf29ce484222325);
+        assert_eq!(
+            fingerprint_bytes(FINGERPRINT_SEED, b"a"),
+            0xaf63dc4c8601ec8c
+        );
+        assert_eq!(
+            fingerprint_bytes(FINGERPRINT_SEED, b"foobar"),
+            0x85944171f73967e8
+        );
+
+        let varint = fingerprint_field(FINGERPRINT_SEED, 1, WireType::Varint);
+        assert_ne!(
+            varint,
+            fingerprint_field(FINGERPRINT_SEED, 2, WireType::Varint)
+        );
+        assert_ne!(
+            varint,
+            fingerprint_field(FINGERPRINT_SEED, 1, WireType::ThirtyTwoBit)
+        );
+        assert_ne!(
+            fingerprint_nested(FINGERPRINT_SEED, varint),
+            fingerprint_nested(FINGERPRINT_SEED, FINGERPRINT_SEED)
+        );
+    }
+
+    #[test]
+    fn peek_key_matches_decode_key() {
+        for (tag, wire_type) in [
+            (1, WireType::Varint),
+            (15, WireType::LengthDelimited),
+            (16, WireType::ThirtyTwoBit),
+            (MAX_TAG, WireType::StartGroup),
+        ] {
+            let mut encoded = Vec::new();
+            encode_key(tag, wire_type, &mut encoded);
+            encoded.push(0x2a);
+
+            let mut buf = encoded.as_slice();
+            assert_eq!(peek_key(&buf), Some(Ok((tag, wire_type))));
+            assert_eq!(buf.len(), encoded.len());
+            assert_eq!(decode_key(&mut buf), Ok((tag, wire_type)));
+            assert_eq!(buf, &[0x2a]);
+        }
+
+        assert_eq!(peek_key(&&[][..]), None);
+        assert!(peek_key(&&[0x00][..]).unwrap().is_err());
+        assert!(peek_key(&&[0x88][..]).unwrap().is_err());
+
+        // A key split across chunks can't be peeked, but decodes normally.
+        let mut split = (&[0x80][..]).chain(&[0x01][..]);
+        assert!(peek_key(&split).unwrap().is_err());
+        assert_eq!(decode_key(&mut split), Ok((16, WireType::Varint)));
+    }
+
+    #[test]
+    fn for_each_repeated_value() {
+        let values: Vec<u32> = (0..10_000).collect();
+        let mut encoded = Vec::new();
+        string::encode(1, &String::from("header"), &mut encoded);
+        uint32::encode_packed(2, &values[..5_000], &mut encoded);
+        fixed32::encode(3, &7, &mut encoded);
+        uint32::encode_repeated(2, &values[5_000..], &mut encoded);
+
+        let mut count = 0;
+        let mut sum = 0u64;
+        uint32::for_each_repeated(2, encoded.as_slice(), |value| {
+            assert_eq!(value, count);
+            count += 1;
+            sum += u64::from(value);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(count, 10_000);
+        assert_eq!(sum, 49_995_000);
+
+        let mut fixed = Vec::new();
+        fixed32::for_each_repeated(3, encoded.as_slice(), |value| {
+            fixed.push(value);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(fixed, [7]);
+
+        // Errors from the callback stop decoding.
+        let mut count = 0;
+        let error = uint32::for_each_repeated(2, encoded.as_slice(), |_| {
+            count += 1;
+            if count == 3 {
+                Err(DecodeError::new("enough"))
+            } else {
+                Ok(())
+            }
+        })
+        .unwrap_err();
+        assert_eq!(error, DecodeError::new("enough"));
+        assert_eq!(count, 3);
+
+        // Values of the wrong wire type are an error.
+        assert!(uint32::for_each_repeated(3, encoded.as_slice(), |_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn skip_nested_groups() {
+        fn skip(depth: usize) -> Result<(), DecodeError> {
+            let mut encoded = Vec::new();
+            for _ in 0..depth {
+                encode_key(1, WireType::StartGroup, &mut encoded);
+            }
+            for _ in 0..depth {
+                encode_key(1, WireType::EndGroup, &mut encoded);
+            }
+            let mut buf = encoded.as_slice();
+            let (tag, wire_type) = decode_key(&mut buf)?;
+            skip_field(wire_type, tag, &mut buf, DecodeContext::default())?;
+            assert!(buf.is_empty());
+            Ok(())
+        }
+
+        skip(crate::SKIP_DEPTH_LIMIT as usize).unwrap();
+        let error = skip(crate::SKIP_DEPTH_LIMIT as usize + 1).unwrap_err();
+        // The skip depth limit applies even when the recursion limit is disabled.
+        #[cfg(feature = "no-recursion-limit")]
+        assert_eq!(
+            error,
+            DecodeError::new("skipped groups are nested too deeply")
+        );
+        #[cfg(not(feature = "no-recursion-limit"))]
+        assert_eq!(error, DecodeError::new("recursion limit reached"));
+    }
+
+    #[test]
+    fn unaligned_fixed_packed() {
+        // Ten bytes of eight-byte elements, followed by another field.
+        let mut encoded = Vec::new();
+        encode_varint(10, &mut encoded);
+        encoded.extend_from_slice(&[0; 10]);
+        encode_key(2, WireType::Varint, &mut encoded);
+        encode_varint(1, &mut encoded);
+
+        let mut values = Vec::new();
+        let error = fixed64::merge_repeated(
+            WireType::LengthDelimited,
+            &mut values,
+            &mut encoded.as_slice(),
+            DecodeContext::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error,
+            DecodeError::new("packed field length 10 is not a multiple of the element size 8")
+        );
+        assert!(values.is_empty());
+
+        let mut values = Vec::new();
+        assert!(sfixed32::merge_repeated(
+            WireType::LengthDelimited,
+            &mut values,
+            &mut &[3, 0, 0, 0][..],
+            DecodeContext::default(),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn encode_packed_iter() {
+        let squares = (0..100u32).map(|i| i * i);
+        let mut encoded = Vec::new();
+        uint32::encode_packed_iter(1, squares.clone(), &mut encoded);
+        fixed64::encode_packed_iter(2, squares.clone().map(u64::from), &mut encoded);
+
+        let mut varints = Vec::new();
+        let mut fixed = Vec::new();
+        let mut buf = encoded.as_slice();
+        let (tag, wire_type) = decode_key(&mut buf).unwrap();
+        assert_eq!(tag, 1);
+        uint32::merge_repeated(wire_type, &mut varints, &mut buf, DecodeContext::default())
+            .unwrap();
+        let (tag, wire_type) = decode_key(&mut buf).unwrap();
+        assert_eq!(tag, 2);
+        fixed64::merge_repeated(wire_type, &mut fixed, &mut buf, DecodeContext::default()).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(varints, squares.clone().collect::<Vec<_>>());
+        assert_eq!(fixed, squares.map(u64::from).collect::<Vec<_>>());
+
+        // Like an empty slice, an empty iterator is not encoded at all.
+        let mut encoded = Vec::new();
+        sint64::encode_packed_iter(1, core::iter::empty(), &mut encoded);
+        sfixed32::encode_packed_iter(2, None, &mut encoded);
+        assert!(encoded.is_empty());
+    }
+
     const U64_MAX_PLUS_ONE: &[u8] = &[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x02];
 
     #[test]
@@ -8,6 +8,8 @@ use alloc::vec::Vec;
 
 use core::fmt;
 
+use crate::encoding::WireType;
+
 /// A Protobuf message decoding error.
 ///
 /// `DecodeError` indicates that the input buffer does not contain a valid
@@ -18,14 +20,29 @@ pub struct DecodeError {
     inner: Box<Inner>,
 }
 
+/// The kind of a [`DecodeError`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum DecodeErrorKind {
+    /// The input is not a well-formed encoding of the message.
+    Malformed,
+    /// The input is well-formed, but the decoded message was rejected by its
+    /// [`Validate`](crate::Validate) implementation.
+    ValidationFailed,
+}
+
 #[derive(Clone, PartialEq, Eq)]
 struct Inner {
     /// A 'best effort' root cause description.
     description: Cow<'static, str>,
+    /// What kind of problem was found.
+    kind: DecodeErrorKind,
     /// A stack of (message, field) name pairs, which identify the specific
     /// message type and field where decoding failed. The stack contains an
     /// entry per level of nesting.
     stack: Vec<(&'static str, &'static str)>,
+    /// The tag and wire type of the innermost field where decoding failed.
+    field_context: Option<(u32, WireType)>,
 }
 
 impl DecodeError {
@@ -38,11 +55,27 @@ impl DecodeError {
         DecodeError {
             inner: Box::new(Inner {
                 description: description.into(),
+                kind: DecodeErrorKind::Malformed,
                 stack: Vec::new(),
+                field_context: None,
             }),
         }
     }
 
+    /// Returns the kind of the error.
+    pub fn kind(&self) -> DecodeErrorKind {
+        self.inner.kind
+    }
+
+    /// Sets the kind of the error.
+    ///
+    /// Meant to be used only by `Message` implementations.
+    #[doc(hidden)]
+    pub fn with_kind(mut self, kind: DecodeErrorKind) -> DecodeError {
+        self.inner.kind = kind;
+        self
+    }
+
     /// Pushes a (message, field) name location pair on to the location stack.
     ///
     /// Meant to be used only by `Message` implementations.
@@ -50,14 +83,41 @@ impl DecodeError {
     pub fn push(&mut self, message: &'static str, field: &'static str) {
         self.inner.stack.push((message, field));
     }
+
+    /// Pushes the location of a field on to the location stack, as a (message, field) name pair.
+    /// The tag and wire type of the first field pushed are kept as the error's field context.
+    ///
+    /// Meant to be used only by `Message` implementations.
+    #[doc(hidden)]
+    #[inline]
+    pub fn push_field(
+        &mut self,
+        message: &'static str,
+        field: &'static str,
+        tag: u32,
+        wire_type: WireType,
+    ) {
+        if self.inner.field_context.is_none() {
+            self.inner.field_context = Some((tag, wire_type));
+        }
+        self.push(message, field);
+    }
+
+    /// Returns the tag and wire type of the innermost field where decoding failed, or `None` if
+    /// the error didn't occur within a field.
+    pub fn field_context(&self) -> Option<(u32, WireType)> {
+        self.inner.field_context
+    }
 }
 
 impl fmt::Debug for DecodeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("DecodeError")
-            .field("description", &self.inner.description)
-            .field("stack", &self.inner.stack)
-            .finish()
+        let mut builder = f.debug_struct("DecodeError");
+        builder.field("description", &self.inner.description);
+        builder.field("kind", &self.inner.kind);
+        builder.field("stack", &self.inner.stack);
+        builder.field("field_context", &self.inner.field_context);
+        builder.finish()
     }
 }
 
@@ -131,3 +191,22 @@ impl From<EncodeError> for std::io::Error {
         std::io::Error::new(std::io::ErrorKind::InvalidInput, error)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use alloc::string::ToString;
+
+    use super::*;
+
+    #[test]
+    fn field_path() {
+        let mut error = DecodeError::new("invalid varint");
+        error.push_field("Inner", "value", 3, WireType::Varint);
+        error.push_field("Outer", "inner", 1, WireType::LengthDelimited);
+        assert_eq!(error.field_context(), Some((3, WireType::Varint)));
+        assert_eq!(
+            error.to_string(),
+            "failed to decode Protobuf message: Inner.value: Outer.inner: invalid varint"
+        );
+    }
+}
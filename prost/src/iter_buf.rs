@@ -0,0 +1,133 @@
+//! A [`Buf`] adapter for decoding from byte iterators.
+
+use bytes::Buf;
+
+/// The number of bytes buffered from the iterator at a time.
+const WINDOW_LEN: usize = 64;
+
+/// A [`Buf`] which pulls its bytes from an iterator.
+///
+/// This allows messages to be decoded from sources which are not contiguous in memory, such as
+/// transports which yield one byte at a time. A small window of bytes is buffered from the
+/// iterator as the buffer is consumed. The iterator must report its exact length, since decoding
+/// relies on [`Buf::remaining`].
+///
+/// ```rust
+/// use prost::{IterBuf, Message};
+///
+/// let bytes = vec![0x08, 0x96, 0x01];
+/// let value = u32::decode(IterBuf::new(bytes.into_iter())).unwrap();
+/// assert_eq!(value, 150);
+/// ```
+#[derive(Debug)]
+pub struct IterBuf<I> {
+    iter: I,
+    window: [u8; WINDOW_LEN],
+    pos: usize,
+    len: usize,
+}
+
+impl<I> IterBuf<I>
+where
+    I: ExactSizeIterator<Item = u8>,
+{
+    /// Creates a new `IterBuf` reading from `iter`.
+    pub fn new(iter: I) -> IterBuf<I> {
+        let mut buf = IterBuf {
+            iter,
+            window: [0; WINDOW_LEN],
+            pos: 0,
+            len: 0,
+        };
+        buf.fill();
+        buf
+    }
+
+    /// Refills the window from the iterator. The window must already be exhausted.
+    fn fill(&mut self) {
+        self.pos = 0;
+        self.len = 0;
+        for (slot, byte) in self.window.iter_mut().zip(&mut self.iter) {
+            *slot = byte;
+            self.len += 1;
+        }
+    }
+}
+
+impl<I> Buf for IterBuf<I>
+where
+    I: ExactSizeIterator<Item = u8>,
+{
+    fn remaining(&self) -> usize {
+        self.len - self.pos + self.iter.len()
+    }
+
+    fn chunk(&self) -> &[u8] {
+        &self.window[self.pos..self.len]
+    }
+
+    fn advance(&mut self, cnt: usize) {
+        assert!(
+            cnt <= self.remaining(),
+            "cannot advance past the end of the buffer"
+        );
+
+        let buffered = self.len - self.pos;
+        if cnt < buffered {
+            self.pos += cnt;
+            return;
+        }
+
+        // Skip the rest of the window and any bytes past it, then refill.
+        if cnt > buffered {
+            self.iter.nth(cnt - buffered - 1);
+        }
+        self.fill();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::Message;
+
+    #[test]
+    fn advance_across_windows() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let mut buf = IterBuf::new(bytes.clone().into_iter());
+        assert_eq!(buf.remaining(), 256);
+        assert_eq!(buf.chunk(), &bytes[..WINDOW_LEN]);
+
+        buf.advance(10);
+        assert_eq!(buf.chunk(), &bytes[10..WINDOW_LEN]);
+
+        buf.advance(WINDOW_LEN - 10);
+        assert_eq!(buf.chunk(), &bytes[WINDOW_LEN..WINDOW_LEN * 2]);
+
+        buf.advance(WINDOW_LEN * 2 + 3);
+        assert_eq!(buf.remaining(), 256 - WINDOW_LEN * 3 - 3);
+        assert_eq!(buf.get_u8(), bytes[WINDOW_LEN * 3 + 3]);
+
+        let mut rest = [0; 256 - WINDOW_LEN * 3 - 4];
+        buf.copy_to_slice(&mut rest);
+        assert_eq!(&rest[..], &bytes[WINDOW_LEN * 3 + 4..]);
+        assert!(!buf.has_remaining());
+        assert!(buf.chunk().is_empty());
+    }
+
+    #[test]
+    fn decode_from_iter() {
+        let value: String = "iterator ".repeat(100);
+        let encoded = value.encode_to_vec();
+        let decoded = String::decode(IterBuf::new(encoded.into_iter())).unwrap();
+        assert_eq!(decoded, value);
+
+        let value: Vec<u8> = (0..200).collect();
+        let mut encoded = value.encode_to_vec();
+        encoded.truncate(encoded.len() - 1);
+        assert!(Vec::<u8>::decode(IterBuf::new(encoded.into_iter())).is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//! A string type which defers UTF-8 validation until it is accessed.
+
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::str::{self, Utf8Error};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+
+use bytes::Bytes;
+
+/// The contents have not been checked yet.
+const UNCHECKED: u8 = 0;
+/// The contents are valid UTF-8.
+const VALID: u8 = 1;
+/// The contents are not valid UTF-8.
+const INVALID: u8 = 2;
+
+/// A string which is checked for UTF-8 well-formedness when it is first accessed, rather than when
+/// it is decoded.
+///
+/// Decoding a `String` field always validates it, which is wasted work for fields that are
+/// usually ignored. `LazyStr` stores the raw bytes instead; a malformed value is only reported
+/// when [`LazyStr::as_str`] is called, and re-encoding a value reproduces its bytes exactly
+/// whether or not it is valid. The outcome of the check is remembered, so valid strings are only
+/// checked once.
+///
+/// Since strings and bytes share one wire format, a protobuf `string` field can be declared as a
+/// `LazyStr` by using the `bytes` attribute:
+///
+/// ```rust
+/// use prost::{LazyStr, Message};
+///
+/// #[derive(Clone, PartialEq, Message)]
+/// struct Record {
+///     #[prost(uint64, tag = "1")]
+///     id: u64,
+///     #[prost(bytes, tag = "2")]
+///     description: LazyStr,
+/// }
+///
+/// let record = Record {
+///     id: 1,
+///     description: LazyStr::from("rarely read"),
+/// };
+/// let decoded = Record::decode(record.encode_to_vec().as_slice()).unwrap();
+/// assert_eq!(decoded.description.as_str(), Ok("rarely read"));
+/// ```
+pub struct LazyStr {
+    bytes: Bytes,
+    utf8: AtomicU8,
+}
+
+impl LazyStr {
+    /// Creates a `LazyStr` from bytes which have not been checked for UTF-8 well-formedness.
+    pub fn from_bytes(bytes: Bytes) -> LazyStr {
+        LazyStr {
+            bytes,
+            utf8: AtomicU8::new(UNCHECKED),
+        }
+    }
+
+    /// Returns the string, or an error if it is not valid UTF-8.
+    pub fn as_str(&self) -> Result<&str, Utf8Error> {
+        if self.utf8.load(Ordering::Relaxed) == VALID {
+            // ## Safety
+            //
+            // The contents never change, and they were found to be valid UTF-8 before `VALID` was
+            // stored.
+            return Ok(unsafe { str::from_utf8_unchecked(&self.bytes) });
+        }
+
+        let result = str::from_utf8(&self.bytes);
+        let utf8 = if result.is_ok() { VALID } else { INVALID };
+        self.utf8.store(utf8, Ordering::Relaxed);
+        result
+    }
+
+    /// Returns the raw bytes of the string, without checking them.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the length of the string in bytes.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns `true` if the string is empty.
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    /// Clears the string, making it empty.
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        *self.utf8.get_mut() = VALID;
+    }
+
+    /// Consumes the `LazyStr`, returning its raw bytes.
+    pub fn into_bytes(self) -> Bytes {
+        self.bytes
+    }
+}
+
+impl Default for LazyStr {
+    fn default() -> LazyStr {
+        LazyStr::from(String::new())
+    }
+}
+
+impl Clone for LazyStr {
+    fn clone(&self) -> LazyStr {
+        LazyStr {
+            bytes: self.bytes.clone(),
+            utf8: AtomicU8::new(self.utf8.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl PartialEq for LazyStr {
+    fn eq(&self, other: &LazyStr) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Eq for LazyStr {}
+
+impl Hash for LazyStr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bytes.hash(state);
+    }
+}
+
+impl AsRef<[u8]> for LazyStr {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl fmt::Debug for LazyStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.as_str() {
+            Ok(s) => fmt::Debug::fmt(s, f),
+            Err(_) => fmt::Debug::fmt(&self.bytes, f),
+        }
+    }
+}
+
+impl From<String> for LazyStr {
+    fn from(s: String) -> LazyStr {
+        LazyStr {
+            bytes: Bytes::from(s),
+            utf8: AtomicU8::new(VALID),
+        }
+    }
+}
+
+impl From<&'static str> for LazyStr {
+    fn from(s: &'static str) -> LazyStr {
+        LazyStr {
+            bytes: Bytes::from_static(s.as_bytes()),
+            utf8: AtomicU8::new(VALID),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::encoding::{bytes, decode_key, DecodeContext};
+
+    fn decode(encoded: &[u8]) -> LazyStr {
+        let mut buf = encoded;
+        let (_, wire_type) = decode_key(&mut buf).unwrap();
+        let mut value = LazyStr::default();
+        bytes::merge(wire_type, &mut value, &mut buf, DecodeContext::default()).unwrap();
+        value
+    }
+
+    #[test]
+    fn validates_on_access() {
+        let mut encoded = Vec::new();
+        bytes::encode(1, &Vec::from(&b"caf\xe9"[..]), &mut encoded);
+
+        // Decoding succeeds; validation only fails once the string is accessed.
+        let value = decode(&encoded);
+        assert_eq!(value.as_bytes(), b"caf\xe9");
+        assert!(value.as_str().is_err());
+        assert!(value.as_str().is_err());
+
+        // Re-encoding reproduces the original bytes.
+        let mut reencoded = Vec::new();
+        bytes::encode(1, &value, &mut reencoded);
+        assert_eq!(reencoded, encoded);
+        assert_eq!(bytes::encoded_len(1, &value), encoded.len());
+    }
+
+    #[test]
+    fn roundtrip_unaccessed() {
+        let mut encoded = Vec::new();
+        bytes::encode(1, &LazyStr::from("café"), &mut encoded);
+
+        let value = decode(&encoded);
+        let mut reencoded = Vec::new();
+        bytes::encode(1, &value, &mut reencoded);
+        assert_eq!(reencoded, encoded);
+
+        assert_eq!(value.as_str(), Ok("café"));
+        assert_eq!(value.clone().as_str(), Ok("café"));
+        assert_eq!(value, LazyStr::from("café"));
+    }
+}
@@ -9,27 +9,55 @@ pub extern crate alloc;
 // Re-export the bytes crate for use within derived code.
 pub use bytes;
 
+mod descriptor;
+#[cfg(feature = "dynamic")]
+mod dynamic;
 mod error;
+mod iter_buf;
+mod lazy_str;
 mod message;
 mod name;
+mod schema;
 mod types;
 
 #[doc(hidden)]
 pub mod encoding;
 
-pub use crate::error::{DecodeError, EncodeError};
-pub use crate::message::Message;
+pub use crate::descriptor::{FieldDescriptor, MessageDescriptor};
+#[cfg(feature = "dynamic")]
+pub use crate::dynamic::{ConversionError, DynValue, Dynamic};
+pub use crate::encoding::WireType;
+pub use crate::error::{DecodeError, DecodeErrorKind, EncodeError};
+pub use crate::iter_buf::IterBuf;
+pub use crate::lazy_str::LazyStr;
+pub use crate::message::{DecodeIter, Message, Validate};
 pub use crate::name::Name;
+pub use crate::schema::{MessageSchema, Oneof};
+
+#[cfg(not(feature = "std"))]
+use alloc::format;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::ops::RangeInclusive;
 
 use bytes::{Buf, BufMut};
 
-use crate::encoding::{decode_varint, encode_varint, encoded_len_varint};
+use crate::encoding::{
+    decode_key, decode_varint, encode_key, encode_varint, encoded_len_varint, skip_field,
+    DecodeContext, MAX_TAG, MIN_TAG,
+};
 
 // See `encoding::DecodeContext` for more info.
 // 100 is the default recursion limit in the C++ implementation.
 #[cfg(not(feature = "no-recursion-limit"))]
 const RECURSION_LIMIT: u32 = 100;
 
+// The deepest nesting of unknown groups which will be skipped. Unlike `RECURSION_LIMIT`, this
+// can't be disabled, since skipped groups don't depend on the recursion of any message type.
+const SKIP_DEPTH_LIMIT: u32 = 100;
+
 /// Encodes a length delimiter to the buffer.
 ///
 /// See [Message.encode_length_delimited] for more info.
@@ -82,6 +110,230 @@ where
     Ok(length as usize)
 }
 
+/// Writes each message to `writer`, prefixed by its length delimiter.
+///
+/// The output is the same as calling [Message.encode_length_delimited] for every message in turn,
+/// so the messages can be read back with [Message.decode_length_delimited]. A single buffer is
+/// reused to encode all of the messages.
+///
+/// ```rust
+/// use prost::Message;
+///
+/// let messages = vec![String::from("first"), String::from("second")];
+/// let mut encoded = Vec::new();
+/// prost::write_all_length_delimited(messages.clone(), &mut encoded).unwrap();
+///
+/// let mut buf = encoded.as_slice();
+/// let mut decoded = Vec::new();
+/// while !buf.is_empty() {
+///     decoded.push(String::decode_length_delimited(&mut buf).unwrap());
+/// }
+/// assert_eq!(decoded, messages);
+/// ```
+#[cfg(feature = "std")]
+pub fn write_all_length_delimited<M, I, W>(messages: I, writer: &mut W) -> std::io::Result<()>
+where
+    M: Message,
+    I: IntoIterator<Item = M>,
+    W: std::io::Write,
+{
+    let mut buf = Vec::new();
+    for message in messages {
+        buf.clear();
+        let len = message.encoded_len();
+        buf.reserve(length_delimiter_len(len) + len);
+        encode_varint(len as u64, &mut buf);
+        message.encode_raw(&mut buf);
+        writer.write_all(&buf)?;
+    }
+    Ok(())
+}
+
+/// Renumbers the fields of an encoded message, without decoding it as any particular message type.
+///
+/// Each field whose tag is a key of `mapping` is given the corresponding value as its new tag, and
+/// all other fields are copied unchanged. This allows payloads encoded with an old schema to be
+/// transcoded after fields have been renumbered. Only the message's own fields are renumbered,
+/// not the fields of nested messages.
+///
+/// An error is returned if the message is malformed, if a new tag is out of range, or if two
+/// different fields would end up with the same tag.
+///
+/// ```rust
+/// use std::collections::BTreeMap;
+///
+/// use prost::Message;
+///
+/// #[derive(Clone, PartialEq, Message)]
+/// struct Old {
+///     #[prost(string, tag = "1")]
+///     name: String,
+///     #[prost(uint32, tag = "2")]
+///     id: u32,
+/// }
+///
+/// #[derive(Clone, PartialEq, Message)]
+/// struct New {
+///     #[prost(uint32, tag = "1")]
+///     id: u32,
+///     #[prost(string, tag = "5")]
+///     name: String,
+/// }
+///
+/// let old = Old { name: "widget".to_string(), id: 3 };
+/// let mapping = BTreeMap::from([(1, 5), (2, 1)]);
+/// let transcoded = prost::transcode_tags(&old.encode_to_vec(), &mapping).unwrap();
+/// let new = New::decode(transcoded.as_slice()).unwrap();
+/// assert_eq!(new, New { id: 3, name: "widget".to_string() });
+/// ```
+pub fn transcode_tags(buf: &[u8], mapping: &BTreeMap<u32, u32>) -> Result<Vec<u8>, DecodeError> {
+    let new_tags = mapping.values().copied().collect::<BTreeSet<u32>>();
+    if new_tags.len() != mapping.len() {
+        return Err(DecodeError::new(
+            "tag mapping gives two fields the same tag",
+        ));
+    }
+    if new_tags
+        .iter()
+        .any(|tag| !(MIN_TAG..=MAX_TAG).contains(tag))
+    {
+        return Err(DecodeError::new("tag mapping contains an invalid tag"));
+    }
+
+    rekey_fields(buf, |tag| match mapping.get(&tag) {
+        Some(&new_tag) => Ok(new_tag),
+        None if new_tags.contains(&tag) => Err(DecodeError::new(format!(
+            "field {} has the same tag as a renumbered field",
+            tag
+        ))),
+        None => Ok(tag),
+    })
+}
+
+/// Returns the tags of the fields of the message in `buf` which fall in any of the `reserved`
+/// ranges, in ascending order and without duplicates.
+///
+/// Derived messages list the tags set aside by their `reserved_tags` attribute as
+/// [`MessageSchema::RESERVED_TAGS`], so this can find data which still uses a retired field:
+///
+/// ```rust
+/// use prost::{Message, MessageSchema};
+///
+/// #[derive(Clone, PartialEq, Message)]
+/// struct Old {
+///     #[prost(uint32, tag = "1")]
+///     id: u32,
+///     #[prost(string, tag = "2")]
+///     nickname: String,
+/// }
+///
+/// #[derive(Clone, PartialEq, Message)]
+/// #[prost(reserved_tags = "2")]
+/// struct New {
+///     #[prost(uint32, tag = "1")]
+///     id: u32,
+/// }
+///
+/// let old = Old { id: 3, nickname: "gadget".to_string() };
+/// let tags = prost::uses_reserved_tags(old.encode_to_vec().as_slice(), New::RESERVED_TAGS);
+/// assert_eq!(tags.unwrap(), [2]);
+/// ```
+pub fn uses_reserved_tags<B>(
+    mut buf: B,
+    reserved: &[RangeInclusive<u32>],
+) -> Result<Vec<u32>, DecodeError>
+where
+    B: Buf,
+{
+    let mut tags = BTreeSet::new();
+    let ctx = DecodeContext::default();
+    while buf.has_remaining() {
+        let (tag, wire_type) = decode_key(&mut buf)?;
+        if reserved.iter().any(|range| range.contains(&tag)) {
+            tags.insert(tag);
+        }
+        skip_field(wire_type, tag, &mut buf, ctx.clone())?;
+    }
+    Ok(tags.into_iter().collect())
+}
+
+/// Re-encodes the message in `buf` with `offset` added to the tag of each of its fields, so that
+/// its fields can be embedded among the fields of another message.
+///
+/// Like [`transcode_tags`], this only re-keys the message's own fields, not the fields of nested
+/// messages. An error is returned if the message is malformed, or if a shifted tag would be out of
+/// range.
+///
+/// ```rust
+/// use prost::Message;
+///
+/// #[derive(Clone, PartialEq, Message)]
+/// struct Point {
+///     #[prost(sint32, tag = "1")]
+///     x: i32,
+///     #[prost(sint32, tag = "2")]
+///     y: i32,
+/// }
+///
+/// #[derive(Clone, PartialEq, Message)]
+/// struct Embedded {
+///     #[prost(sint32, tag = "101")]
+///     x: i32,
+///     #[prost(sint32, tag = "102")]
+///     y: i32,
+/// }
+///
+/// let point = Point { x: 1, y: -1 };
+/// let shifted = prost::offset_tags(&point.encode_to_vec(), 100).unwrap();
+/// assert_eq!(Embedded::decode(shifted.as_slice()).unwrap(), Embedded { x: 1, y: -1 });
+/// ```
+pub fn offset_tags(buf: &[u8], offset: u32) -> Result<Vec<u8>, DecodeError> {
+    rekey_fields(buf, |tag| {
+        tag.checked_add(offset)
+            .filter(|&tag| tag <= MAX_TAG)
+            .ok_or_else(|| {
+                DecodeError::new(format!("tag {} overflowed when offset by {}", tag, offset))
+            })
+    })
+}
+
+/// Re-encodes the message in `buf`, giving each of its fields the tag returned by `new_tag`.
+fn rekey_fields<F>(mut buf: &[u8], mut new_tag: F) -> Result<Vec<u8>, DecodeError>
+where
+    F: FnMut(u32) -> Result<u32, DecodeError>,
+{
+    let mut rekeyed = Vec::with_capacity(buf.len());
+    let ctx = DecodeContext::default();
+    while !buf.is_empty() {
+        let (tag, wire_type) = decode_key(&mut buf)?;
+        let new_tag = new_tag(tag)?;
+
+        encode_key(new_tag, wire_type, &mut rekeyed);
+        let field = buf;
+        if wire_type == WireType::StartGroup {
+            // A group ends with a key holding its tag, which has to be renumbered too. The end key
+            // may be padded, so the group's fields are read to find where it starts.
+            let end = loop {
+                let key_start = buf;
+                let (inner_tag, inner_wire_type) = decode_key(&mut buf)?;
+                if inner_wire_type == WireType::EndGroup {
+                    if inner_tag != tag {
+                        return Err(DecodeError::new("unexpected end group tag"));
+                    }
+                    break field.len() - key_start.len();
+                }
+                skip_field(inner_wire_type, inner_tag, &mut buf, ctx.enter_recursion())?;
+            };
+            rekeyed.extend_from_slice(&field[..end]);
+            encode_key(new_tag, WireType::EndGroup, &mut rekeyed);
+        } else {
+            skip_field(wire_type, tag, &mut buf, ctx.clone())?;
+            rekeyed.extend_from_slice(&field[..field.len() - buf.len()]);
+        }
+    }
+    Ok(rekeyed)
+}
+
 // Re-export #[derive(Message, Enumeration, Oneof)].
 // Based on serde's equivalent re-export [1], but enabled by default.
 //
@@ -93,3 +345,123 @@ extern crate prost_derive;
 #[cfg(feature = "derive")]
 #[doc(hidden)]
 pub use prost_derive::*;
+
+#[cfg(test)]
+mod test {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn length_delimiter_width() {
+        let cases: &[(usize, usize)] = &[
+            (0, 1),
+            (0x7f, 1),
+            (0x80, 2),
+            (0x3fff, 2),
+            (0x4000, 3),
+            (0x1f_ffff, 3),
+            (0x20_0000, 4),
+            (0xfff_ffff, 4),
+            (0x1000_0000, 5),
+            (u32::MAX as usize, 5),
+        ];
+        for &(length, width) in cases {
+            assert_eq!(length_delimiter_len(length), width, "length {:#x}", length);
+
+            let mut buf = Vec::new();
+            encode_length_delimiter(length, &mut buf).unwrap();
+            assert_eq!(buf.len(), width, "length {:#x}", length);
+            assert_eq!(decode_length_delimiter(buf.as_slice()).unwrap(), length);
+
+            // Encoding fails without writing anything if the delimiter doesn't fit.
+            let mut storage = [0u8; 10];
+            let mut short = &mut storage[..width - 1];
+            let error = encode_length_delimiter(length, &mut short).unwrap_err();
+            assert_eq!(error.required_capacity(), width);
+            assert_eq!(storage, [0; 10]);
+        }
+
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(length_delimiter_len(usize::MAX), 10);
+    }
+
+    #[test]
+    fn transcode_tags_renumbers_fields() {
+        use crate::encoding::{group, string, uint32};
+
+        #[cfg(not(feature = "std"))]
+        use alloc::string::String;
+
+        let mut encoded = Vec::new();
+        uint32::encode(1, &7, &mut encoded);
+        string::encode(2, &String::from("kept"), &mut encoded);
+        group::encode(3, &String::from("grouped"), &mut encoded);
+        uint32::encode(1, &8, &mut encoded);
+
+        let mapping = BTreeMap::from([(1, 20), (3, 4)]);
+        let transcoded = transcode_tags(&encoded, &mapping).unwrap();
+
+        let mut expected = Vec::new();
+        uint32::encode(20, &7, &mut expected);
+        string::encode(2, &String::from("kept"), &mut expected);
+        group::encode(4, &String::from("grouped"), &mut expected);
+        uint32::encode(20, &8, &mut expected);
+        assert_eq!(transcoded, expected);
+
+        // Renumbering back restores the original payload.
+        let inverse = BTreeMap::from([(20, 1), (4, 3)]);
+        assert_eq!(transcode_tags(&transcoded, &inverse).unwrap(), encoded);
+
+        // Fields may not be renumbered onto the same tag as another field.
+        assert!(transcode_tags(&encoded, &BTreeMap::from([(1, 2)])).is_err());
+        assert!(transcode_tags(&encoded, &BTreeMap::from([(1, 5), (3, 5)])).is_err());
+        assert!(transcode_tags(&encoded, &BTreeMap::from([(1, 0)])).is_err());
+        assert!(transcode_tags(&encoded, &BTreeMap::from([(1, MAX_TAG + 1)])).is_err());
+
+        // Malformed messages are reported.
+        assert!(transcode_tags(&encoded[..encoded.len() - 1], &mapping).is_err());
+    }
+
+    #[test]
+    fn offset_tags_shifts_fields() {
+        use crate::encoding::{group, string, uint32};
+
+        #[cfg(not(feature = "std"))]
+        use alloc::string::String;
+
+        let mut encoded = Vec::new();
+        uint32::encode(1, &7, &mut encoded);
+        string::encode(2, &String::from("named"), &mut encoded);
+        group::encode(3, &String::from("grouped"), &mut encoded);
+
+        let shifted = offset_tags(&encoded, 1000).unwrap();
+        let mut expected = Vec::new();
+        uint32::encode(1001, &7, &mut expected);
+        string::encode(1002, &String::from("named"), &mut expected);
+        group::encode(1003, &String::from("grouped"), &mut expected);
+        assert_eq!(shifted, expected);
+        assert_eq!(offset_tags(&encoded, 0).unwrap(), encoded);
+
+        assert_eq!(
+            offset_tags(&encoded, MAX_TAG - 2).unwrap_err(),
+            DecodeError::new(format!("tag 3 overflowed when offset by {}", MAX_TAG - 2))
+        );
+        assert!(offset_tags(&encoded, u32::MAX).is_err());
+        assert!(offset_tags(&encoded[..encoded.len() - 1], 1).is_err());
+    }
+
+    #[test]
+    fn rekey_group_with_padded_end_key() {
+        // Group 3 holds field 1 with the value 7, and its end key is padded to two bytes.
+        let encoded = [0x1b, 0x08, 0x07, 0x9c, 0x00];
+        assert_eq!(offset_tags(&encoded, 1).unwrap(), [0x23, 0x08, 0x07, 0x24]);
+        assert_eq!(
+            transcode_tags(&encoded, &BTreeMap::from([(3, 5)])).unwrap(),
+            [0x2b, 0x08, 0x07, 0x2c]
+        );
+
+        // The end key has to match the group's tag.
+        assert!(offset_tags(&[0x1b, 0x08, 0x07, 0xa4, 0x00], 1).is_err());
+    }
+}
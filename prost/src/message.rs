@@ -1,14 +1,17 @@
 #[cfg(not(feature = "std"))]
 use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
 #[cfg(not(feature = "std"))]
 use alloc::vec::Vec;
 
 use core::fmt::Debug;
+use core::iter::FusedIterator;
+use core::marker::PhantomData;
 
 use bytes::{Buf, BufMut};
 
 use crate::encoding::{
-    decode_key, encode_varint, encoded_len_varint, message, DecodeContext, WireType,
+    decode_key, encode_varint, encoded_len_varint, message, skip_field, DecodeContext, WireType,
 };
 use crate::DecodeError;
 use crate::EncodeError;
@@ -46,7 +49,9 @@ pub trait Message: Debug + Send + Sync {
 
     /// Encodes the message to a buffer.
     ///
-    /// An error will be returned if the buffer does not have sufficient capacity.
+    /// An error will be returned if the buffer does not have sufficient capacity, in which case
+    /// nothing is written. This makes it safe to use with fixed-capacity buffers such as
+    /// `&mut [u8]`.
     fn encode<B>(&self, buf: &mut B) -> Result<(), EncodeError>
     where
         B: BufMut,
@@ -70,9 +75,63 @@ pub trait Message: Debug + Send + Sync {
         let mut buf = Vec::with_capacity(self.encoded_len());
 
         self.encode_raw(&mut buf);
+        #[cfg(all(feature = "strict-encode-checks", debug_assertions))]
+        check_encoded(&buf, self.encoded_len());
         buf
     }
 
+    /// Encodes the message to a newly allocated buffer, omitting the fields whose tags are in
+    /// `skip`, for example to redact them.
+    ///
+    /// The remaining fields keep their order, and decoding the result leaves the skipped fields
+    /// at their defaults. Derived messages skip the fields as they are encoded and never fail.
+    /// Other implementations are encoded in full and have the skipped fields cut out of the
+    /// encoding, which fails if the encoding can't be read back.
+    fn encode_filtered_to_vec(&self, skip: &BTreeSet<u32>) -> Result<Vec<u8>, DecodeError>
+    where
+        Self: Sized,
+    {
+        let mut buf = Vec::with_capacity(self.encoded_len());
+        self.encode_raw_filtered(skip, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Encodes the message to a buffer like `encode_raw`, omitting the fields whose tags are in
+    /// `skip`.
+    ///
+    /// The default implementation encodes the whole message and cuts the skipped fields out of
+    /// the encoding, failing if the encoding can't be read back. Derived implementations skip the
+    /// fields as they encode them instead.
+    ///
+    /// Meant to be used only by `Message` implementations.
+    #[doc(hidden)]
+    fn encode_raw_filtered<B>(&self, skip: &BTreeSet<u32>, buf: &mut B) -> Result<(), DecodeError>
+    where
+        B: BufMut,
+        Self: Sized,
+    {
+        if skip.is_empty() {
+            self.encode_raw(buf);
+            return Ok(());
+        }
+
+        let mut encoded = Vec::with_capacity(self.encoded_len());
+        self.encode_raw(&mut encoded);
+
+        let mut filtered = Vec::with_capacity(encoded.len());
+        let mut fields = encoded.as_slice();
+        while !fields.is_empty() {
+            let start = encoded.len() - fields.len();
+            let (tag, wire_type) = decode_key(&mut fields)?;
+            skip_field(wire_type, tag, &mut fields, DecodeContext::default())?;
+            if !skip.contains(&tag) {
+                filtered.extend_from_slice(&encoded[start..encoded.len() - fields.len()]);
+            }
+        }
+        buf.put_slice(&filtered);
+        Ok(())
+    }
+
     /// Encodes the message with a length-delimiter to a buffer.
     ///
     /// An error will be returned if the buffer does not have sufficient capacity.
@@ -102,12 +161,17 @@ pub trait Message: Debug + Send + Sync {
 
         encode_varint(len as u64, &mut buf);
         self.encode_raw(&mut buf);
+        #[cfg(all(feature = "strict-encode-checks", debug_assertions))]
+        check_encoded(&buf[encoded_len_varint(len as u64)..], len);
         buf
     }
 
     /// Decodes an instance of the message from a buffer.
     ///
-    /// The entire buffer will be consumed.
+    /// The entire buffer will be consumed. Since a message has no end marker, any bytes after its
+    /// fields are decoded as further fields, so trailing bytes which are not valid fields cause an
+    /// error rather than being ignored. To decode a message which is followed by other data, use
+    /// [`Message::decode_length_delimited`].
     fn decode<B>(mut buf: B) -> Result<Self, DecodeError>
     where
         B: Buf,
@@ -117,6 +181,90 @@ pub trait Message: Debug + Send + Sync {
         Self::merge(&mut message, &mut buf).map(|_| message)
     }
 
+    /// Decodes an instance of the message from a buffer, failing if the strings, bytes, repeated
+    /// and map fields being decoded into request more than `limit` bytes of memory in total.
+    ///
+    /// This bounds the memory a decode can use independently of the size of the input, which is
+    /// useful when fuzzing or when handling untrusted data. The entire buffer will be consumed.
+    #[cfg(feature = "decode-metrics")]
+    fn decode_with_allocation_limit<B>(mut buf: B, limit: usize) -> Result<Self, DecodeError>
+    where
+        B: Buf,
+        Self: Default,
+    {
+        let mut message = Self::default();
+        let ctx = DecodeContext::with_allocation_limit(limit);
+        while buf.has_remaining() {
+            let (tag, wire_type) = decode_key(&mut buf)?;
+            message.merge_field(tag, wire_type, &mut buf, ctx.clone())?;
+        }
+        message.validate_decoded()?;
+        Ok(message)
+    }
+
+    /// Decodes an instance of the message from a buffer, failing if any string or bytes field is
+    /// longer than `len` bytes.
+    ///
+    /// Oversized values are rejected before they are copied, which guards against a single huge
+    /// field more tightly than a limit on the whole message. The entire buffer will be consumed.
+    #[cfg(feature = "decode-metrics")]
+    fn decode_with_max_value_len<B>(mut buf: B, len: usize) -> Result<Self, DecodeError>
+    where
+        B: Buf,
+        Self: Default,
+    {
+        let mut message = Self::default();
+        let ctx = DecodeContext::with_max_value_len(len);
+        while buf.has_remaining() {
+            let (tag, wire_type) = decode_key(&mut buf)?;
+            message.merge_field(tag, wire_type, &mut buf, ctx.clone())?;
+        }
+        message.validate_decoded()?;
+        Ok(message)
+    }
+
+    /// Decodes as much of a message as possible from a buffer, such as one holding a record which
+    /// was truncated by a crash.
+    ///
+    /// Fields are decoded in order until the buffer is exhausted or a field fails to decode. The
+    /// message holding every field before the failed one is returned along with the error, if
+    /// any. The failed field is left out entirely, so a partly decoded nested message, string or
+    /// repeated field is never returned. Messages which did not decode completely are not
+    /// validated.
+    fn decode_partial<B>(buf: B) -> (Self, Option<DecodeError>)
+    where
+        B: Buf + Clone,
+        Self: Default,
+    {
+        let mut message = Self::default();
+        let ctx = DecodeContext::default();
+        let len = buf.remaining();
+        let mut fields = buf.clone();
+        let mut decoded_len = 0;
+        while fields.has_remaining() {
+            let result = decode_key(&mut fields).and_then(|(tag, wire_type)| {
+                message.merge_field(tag, wire_type, &mut fields, ctx.clone())
+            });
+            if let Err(error) = result {
+                // The failed field may have been partly merged, so decode the fields before it
+                // again into a fresh message.
+                let mut message = Self::default();
+                let mut prefix = buf.take(decoded_len);
+                while prefix.has_remaining() {
+                    let (tag, wire_type) =
+                        decode_key(&mut prefix).expect("field key decoded previously");
+                    message
+                        .merge_field(tag, wire_type, &mut prefix, ctx.clone())
+                        .expect("field decoded previously");
+                }
+                return (message, Some(error));
+            }
+            decoded_len = len - fields.remaining();
+        }
+        let error = message.validate_decoded().err();
+        (message, error)
+    }
+
     /// Decodes a length-delimited instance of the message from the buffer.
     fn decode_length_delimited<B>(buf: B) -> Result<Self, DecodeError>
     where
@@ -128,6 +276,40 @@ pub trait Message: Debug + Send + Sync {
         Ok(message)
     }
 
+    /// Decodes a length-delimited instance of the message from the start of a buffer which may
+    /// hold other data after it.
+    ///
+    /// Returns the message along with the number of bytes it occupied, including its length
+    /// delimiter, which is the offset in the buffer where the remaining data starts.
+    fn decode_length_delimited_with_tail<B>(mut buf: B) -> Result<(Self, usize), DecodeError>
+    where
+        B: Buf,
+        Self: Default,
+    {
+        let remaining = buf.remaining();
+        let message = Self::decode_length_delimited(&mut buf)?;
+        Ok((message, remaining - buf.remaining()))
+    }
+
+    /// Returns an iterator which decodes consecutive length-delimited instances of the message
+    /// from a buffer.
+    ///
+    /// The iterator yields one message per call to `next`, and ends once the buffer is exhausted.
+    /// If a message fails to decode, for example because the buffer ends partway through it, the
+    /// error is yielded and the iterator ends.
+    fn decode_many_length_delimited<B>(buf: B) -> DecodeIter<B, Self>
+    where
+        B: Buf,
+        Self: Default + Sized,
+    {
+        DecodeIter {
+            buf,
+            ctx: DecodeContext::default(),
+            done: false,
+            _message: PhantomData,
+        }
+    }
+
     /// Decodes an instance of the message from a buffer, and merges it into `self`.
     ///
     /// The entire buffer will be consumed.
@@ -141,7 +323,7 @@ pub trait Message: Debug + Send + Sync {
             let (tag, wire_type) = decode_key(&mut buf)?;
             self.merge_field(tag, wire_type, &mut buf, ctx.clone())?;
         }
-        Ok(())
+        self.validate_decoded()
     }
 
     /// Decodes a length-delimited instance of the message from buffer, and
@@ -156,10 +338,25 @@ pub trait Message: Debug + Send + Sync {
             self,
             &mut buf,
             DecodeContext::default(),
-        )
+        )?;
+        self.validate_decoded()
+    }
+
+    /// Checks the message once it has been decoded, returning an error if it is invalid.
+    ///
+    /// This is called once at the end of every decode and merge, after all of the input has been
+    /// merged, so a nested message which is split across several chunks of input is only checked
+    /// once it is complete. The default implementation accepts every message. Derived
+    /// implementations for messages annotated with `#[prost(validate)]` check their nested
+    /// messages first, then call [`Validate::validate`]; other derived messages keep the default.
+    fn validate_decoded(&self) -> Result<(), DecodeError> {
+        Ok(())
     }
 
     /// Clears the message, resetting all fields to their default.
+    ///
+    /// Derived implementations empty string, bytes, repeated and map fields in place, so their
+    /// allocated capacity is kept and a message can be reused for decoding without reallocating.
     fn clear(&mut self);
 }
 
@@ -173,6 +370,12 @@ where
     {
         (**self).encode_raw(buf)
     }
+    fn encode_raw_filtered<B>(&self, skip: &BTreeSet<u32>, buf: &mut B) -> Result<(), DecodeError>
+    where
+        B: BufMut,
+    {
+        (**self).encode_raw_filtered(skip, buf)
+    }
     fn merge_field<B>(
         &mut self,
         tag: u32,
@@ -188,14 +391,322 @@ where
     fn encoded_len(&self) -> usize {
         (**self).encoded_len()
     }
+    fn validate_decoded(&self) -> Result<(), DecodeError> {
+        (**self).validate_decoded()
+    }
     fn clear(&mut self) {
         (**self).clear()
     }
 }
 
+/// An iterator over length-delimited messages decoded from a buffer.
+///
+/// This is returned by [`Message::decode_many_length_delimited`].
+#[derive(Debug)]
+pub struct DecodeIter<B, M> {
+    buf: B,
+    ctx: DecodeContext,
+    done: bool,
+    _message: PhantomData<fn() -> M>,
+}
+
+impl<B, M> Iterator for DecodeIter<B, M>
+where
+    B: Buf,
+    M: Message + Default,
+{
+    type Item = Result<M, DecodeError>;
+
+    fn next(&mut self) -> Option<Result<M, DecodeError>> {
+        if self.done || !self.buf.has_remaining() {
+            return None;
+        }
+
+        let mut message = M::default();
+        let result = message::merge(
+            WireType::LengthDelimited,
+            &mut message,
+            &mut self.buf,
+            self.ctx.clone(),
+        )
+        .and_then(|()| message.validate_decoded());
+        match result {
+            Ok(()) => Some(Ok(message)),
+            Err(error) => {
+                self.done = true;
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+impl<B, M> FusedIterator for DecodeIter<B, M>
+where
+    B: Buf,
+    M: Message + Default,
+{
+}
+
+/// Invariants of a message which are checked after it is decoded.
+///
+/// Some invariants, such as one field being no greater than another, can't be expressed in the
+/// message's types. Implement this trait for a message and annotate it with `#[prost(validate)]`
+/// to have every decode of the message fail when [`Validate::validate`] returns an error. A nested
+/// message is checked when the message containing it is also annotated with
+/// `#[prost(validate)]`, so that messages which don't opt in cost nothing. The error's kind is set to
+/// [`DecodeErrorKind::ValidationFailed`](crate::DecodeErrorKind::ValidationFailed), which
+/// distinguishes it from an error in the encoding of the message.
+///
+/// ```rust
+/// use prost::{DecodeError, Message, Validate};
+///
+/// #[derive(Clone, PartialEq, Message)]
+/// #[prost(validate)]
+/// struct Range {
+///     #[prost(uint64, tag = "1")]
+///     start: u64,
+///     #[prost(uint64, tag = "2")]
+///     end: u64,
+/// }
+///
+/// impl Validate for Range {
+///     fn validate(&self) -> Result<(), DecodeError> {
+///         if self.start <= self.end {
+///             Ok(())
+///         } else {
+///             Err(DecodeError::new("range start is after its end"))
+///         }
+///     }
+/// }
+///
+/// let range = Range { start: 2, end: 1 };
+/// assert!(Range::decode(range.encode_to_vec().as_slice()).is_err());
+/// ```
+pub trait Validate {
+    /// Returns an error if the message is invalid.
+    fn validate(&self) -> Result<(), DecodeError>;
+}
+
+/// Panics if `encoded` is not a well-formed message of `encoded_len` bytes whose keys are all
+/// minimally encoded.
+#[cfg(all(feature = "strict-encode-checks", debug_assertions))]
+fn check_encoded(mut encoded: &[u8], encoded_len: usize) {
+    assert_eq!(
+        encoded.len(),
+        encoded_len,
+        "message was encoded with a different length than its encoded_len"
+    );
+    let ctx = DecodeContext::default();
+    while !encoded.is_empty() {
+        let remaining = encoded.len();
+        let (tag, wire_type) = decode_key(&mut encoded)
+            .unwrap_or_else(|error| panic!("message was encoded with a malformed key: {}", error));
+        assert_eq!(
+            remaining - encoded.len(),
+            crate::encoding::key_len(tag),
+            "field {} was encoded with a non-minimal key",
+            tag
+        );
+        skip_field(wire_type, tag, &mut encoded, ctx.clone())
+            .unwrap_or_else(|error| panic!("field {} was malformed: {}", tag, error));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     const _MESSAGE_IS_OBJECT_SAFE: Option<&dyn Message> = None;
+
+    #[test]
+    #[cfg(all(feature = "strict-encode-checks", debug_assertions))]
+    #[should_panic(expected = "field 1 was encoded with a non-minimal key")]
+    fn strict_encode_checks_reject_non_minimal_keys() {
+        /// Encodes a single `uint32` field with a key padded to two bytes.
+        #[derive(Debug)]
+        struct PaddedKey;
+
+        impl Message for PaddedKey {
+            fn encode_raw<B>(&self, buf: &mut B)
+            where
+                B: BufMut,
+            {
+                buf.put_slice(&[0x88, 0x00, 0x01]);
+            }
+
+            fn merge_field<B>(
+                &mut self,
+                tag: u32,
+                wire_type: WireType,
+                buf: &mut B,
+                ctx: DecodeContext,
+            ) -> Result<(), DecodeError>
+            where
+                B: Buf,
+            {
+                skip_field(wire_type, tag, buf, ctx)
+            }
+
+            fn encoded_len(&self) -> usize {
+                3
+            }
+
+            fn clear(&mut self) {}
+        }
+
+        // The padded key is still decodable, so only the strict checks catch it.
+        assert!(<()>::decode(&[0x88, 0x00, 0x01][..]).is_ok());
+        PaddedKey.encode_to_vec();
+    }
+
+    #[test]
+    fn encode_filtered_reports_unreadable_encodings() {
+        /// Encodes a key with the invalid wire type 7.
+        #[derive(Debug)]
+        struct BadWireType;
+
+        impl Message for BadWireType {
+            fn encode_raw<B>(&self, buf: &mut B)
+            where
+                B: BufMut,
+            {
+                buf.put_u8(0x0f);
+            }
+
+            fn merge_field<B>(
+                &mut self,
+                tag: u32,
+                wire_type: WireType,
+                buf: &mut B,
+                ctx: DecodeContext,
+            ) -> Result<(), DecodeError>
+            where
+                B: Buf,
+            {
+                skip_field(wire_type, tag, buf, ctx)
+            }
+
+            fn encoded_len(&self) -> usize {
+                1
+            }
+
+            fn clear(&mut self) {}
+        }
+
+        assert_eq!(
+            BadWireType.encode_filtered_to_vec(&BTreeSet::new()),
+            Ok(alloc::vec![0x0f])
+        );
+        assert!(BadWireType
+            .encode_filtered_to_vec(&BTreeSet::from([1]))
+            .is_err());
+    }
+
+    #[test]
+    fn encode_insufficient_capacity() {
+        #[cfg(not(feature = "std"))]
+        use alloc::string::String;
+
+        let message = String::from("hello");
+        let mut storage = [0u8; 7];
+
+        let mut buf = &mut storage[..3];
+        let error = message.encode(&mut buf).unwrap_err();
+        assert_eq!(error.required_capacity(), 7);
+        assert_eq!(error.remaining(), 3);
+        assert_eq!(buf.len(), 3);
+        assert_eq!(storage, [0; 7]);
+
+        let mut buf = &mut storage[..6];
+        let error = message.encode_length_delimited(&mut buf).unwrap_err();
+        assert_eq!(error.required_capacity(), 8);
+        assert_eq!(error.remaining(), 6);
+
+        let mut buf = &mut storage[..];
+        message.encode(&mut buf).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(storage, *b"\x0a\x05hello");
+    }
+
+    #[test]
+    fn decode_trailing_bytes() {
+        #[cfg(not(feature = "std"))]
+        use alloc::string::{String, ToString};
+
+        let first = String::from("first").encode_to_vec();
+        let second = String::from("second").encode_to_vec();
+
+        // Trailing bytes which aren't a valid field are an error.
+        let mut encoded = first.clone();
+        encoded.push(0xff);
+        assert!(String::decode(encoded.as_slice()).is_err());
+
+        // Trailing bytes which are valid fields are decoded as part of the message.
+        let encoded = [&first[..], &second[..]].concat();
+        let mut buf = encoded.as_slice();
+        assert_eq!(String::decode(&mut buf).unwrap(), "second".to_string());
+        assert!(buf.is_empty());
+
+        // A length-delimited message leaves the bytes after it in the buffer.
+        let mut encoded = String::from("first").encode_length_delimited_to_vec();
+        encoded.extend_from_slice(&second);
+        let mut buf = encoded.as_slice();
+        assert_eq!(
+            String::decode_length_delimited(&mut buf).unwrap(),
+            "first".to_string()
+        );
+        assert_eq!(buf, &second[..]);
+    }
+
+    #[test]
+    fn decode_many_length_delimited() {
+        #[cfg(not(feature = "std"))]
+        use alloc::string::{String, ToString};
+
+        let messages: Vec<String> = (0..5).map(|i| "message ".repeat(i)).collect();
+        let mut encoded = Vec::new();
+        for message in &messages {
+            message.encode_length_delimited(&mut encoded).unwrap();
+        }
+
+        let decoded = String::decode_many_length_delimited(encoded.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(decoded, messages);
+
+        // A truncated final message is reported as an error, which ends the iteration.
+        encoded.pop();
+        let mut iter = String::decode_many_length_delimited(encoded.as_slice());
+        for message in &messages[..4] {
+            assert_eq!(iter.next().as_ref(), Some(&Ok(message.to_string())));
+        }
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+
+        assert!(String::decode_many_length_delimited(&[][..])
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn decode_length_delimited_with_tail() {
+        #[cfg(not(feature = "std"))]
+        use alloc::string::{String, ToString};
+
+        let mut encoded = String::from("message").encode_length_delimited_to_vec();
+        let len = encoded.len();
+        encoded.extend_from_slice(b"\xff\xfftail");
+
+        let (message, offset) = String::decode_length_delimited_with_tail(&encoded[..]).unwrap();
+        assert_eq!(message, "message".to_string());
+        assert_eq!(offset, len);
+        assert_eq!(&encoded[offset..], b"\xff\xfftail");
+
+        // The whole message must be present, even though the tail may be empty.
+        assert_eq!(
+            String::decode_length_delimited_with_tail(&encoded[..len]).unwrap(),
+            ("message".to_string(), len)
+        );
+        assert!(String::decode_length_delimited_with_tail(&encoded[..len - 1]).is_err());
+    }
 }
@@ -0,0 +1,61 @@
+//! Compile-time facts about the schemas of derived messages and oneofs.
+
+use core::ops::RangeInclusive;
+
+use crate::descriptor::FieldDescriptor;
+use crate::DecodeError;
+
+/// Facts about a message's schema, implemented by `#[derive(Message)]`.
+///
+/// These are associated constants, so they cost nothing unless they are used, and they can't
+/// collide with the names of a message's own methods.
+///
+/// ```rust
+/// use prost::{Message, MessageSchema};
+///
+/// #[derive(Clone, PartialEq, Message)]
+/// #[prost(reserved_tags = "3")]
+/// struct Point {
+///     #[prost(sint32, tag = "1")]
+///     x: i32,
+///     #[prost(sint32, tag = "2")]
+///     y: i32,
+/// }
+///
+/// assert_eq!(Point::MAX_TAG, 2);
+/// assert_eq!(Point::RESERVED_TAGS, [3..=3]);
+/// assert_eq!(Point::MAX_ENCODED_LEN, Some(12));
+/// ```
+pub trait MessageSchema {
+    /// The largest number of bytes an encoded message can occupy, or `None` if it has fields of
+    /// unbounded length.
+    const MAX_ENCODED_LEN: Option<usize>;
+
+    /// The largest tag of any field of the message, including oneof fields, or `0` if it has no
+    /// fields.
+    const MAX_TAG: u32;
+
+    /// The ranges of tags reserved by the message's `reserved_tags` attribute, which its fields
+    /// may not use.
+    const RESERVED_TAGS: &'static [RangeInclusive<u32>];
+
+    /// A fingerprint of the field tags and wire types of the message, which changes whenever the
+    /// set of `(tag, wire type)` pairs does.
+    const SCHEMA_FINGERPRINT: u64;
+}
+
+/// A type which can be the value of a oneof field, implemented by `#[derive(Oneof)]`.
+pub trait Oneof {
+    /// A fingerprint of the variant tags and wire types of the oneof.
+    const SCHEMA_FINGERPRINT: u64;
+
+    /// Descriptions of the variants of the oneof, in tag order.
+    const FIELD_DESCRIPTORS: &'static [FieldDescriptor];
+
+    /// Returns the tag of the current variant.
+    fn tag(&self) -> u32;
+
+    /// Checks the messages held by the current variant once the oneof's message has been
+    /// decoded, as [`Message::validate_decoded`](crate::Message::validate_decoded) does.
+    fn validate_decoded(&self) -> Result<(), DecodeError>;
+}
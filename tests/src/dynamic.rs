@@ -0,0 +1,95 @@
+//! Tests for converting messages to and from dynamic values.
+
+use prost::alloc::collections::BTreeMap;
+use prost::alloc::vec;
+#[cfg(not(feature = "std"))]
+use prost::alloc::{borrow::ToOwned, boxed::Box, string::String, vec::Vec};
+use prost::bytes::Bytes;
+use prost::{ConversionError, DynValue, Message};
+
+#[derive(Clone, PartialEq, Message)]
+#[prost(dynamic)]
+struct Record {
+    #[prost(int64, tag = "1")]
+    id: i64,
+    #[prost(fixed32, tag = "2")]
+    flags: u32,
+    #[prost(double, optional, tag = "3")]
+    score: Option<f64>,
+    #[prost(string, tag = "4")]
+    name: String,
+    #[prost(bytes = "bytes", tag = "5")]
+    payload: Bytes,
+    #[prost(bool, repeated, tag = "6")]
+    votes: Vec<bool>,
+    #[prost(message, optional, tag = "7")]
+    parent: Option<Box<Record>>,
+    #[prost(message, repeated, tag = "8")]
+    children: Vec<Record>,
+    #[prost(enumeration = "Status", tag = "9")]
+    status: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+enum Status {
+    Unknown = 0,
+    Active = 1,
+}
+
+#[test]
+fn dynamic_roundtrip() {
+    let record = Record {
+        id: -5,
+        flags: u32::MAX,
+        score: Some(0.5),
+        name: "root".to_owned(),
+        payload: Bytes::from_static(b"\x00\x01"),
+        votes: vec![true, false],
+        parent: Some(Box::new(Record::default())),
+        children: vec![Record {
+            name: "child".to_owned(),
+            ..Record::default()
+        }],
+        status: Status::Active as i32,
+    };
+
+    let map = record.to_dynamic();
+    assert_eq!(map[&1], DynValue::Int(-5));
+    assert_eq!(map[&2], DynValue::Uint(u32::MAX.into()));
+    assert_eq!(map[&3], DynValue::Float(0.5));
+    assert_eq!(map[&4], DynValue::String("root".to_owned()));
+    assert_eq!(map[&5], DynValue::Bytes(vec![0, 1]));
+    assert_eq!(
+        map[&6],
+        DynValue::List(vec![DynValue::Bool(true), DynValue::Bool(false)])
+    );
+    assert!(matches!(map[&7], DynValue::Message(_)));
+    assert_eq!(map[&9], DynValue::Int(1));
+    assert_eq!(Record::from_dynamic(map), Ok(record));
+
+    // Unset optional fields are left out, and missing fields are left at their defaults.
+    let map = Record::default().to_dynamic();
+    assert!(!map.contains_key(&3) && !map.contains_key(&7));
+    assert_eq!(Record::from_dynamic(BTreeMap::new()), Ok(Record::default()));
+}
+
+#[test]
+fn dynamic_errors() {
+    let mut map = BTreeMap::new();
+    map.insert(10, DynValue::Int(1));
+    let error = Record::from_dynamic(map).unwrap_err();
+    assert_eq!(error, ConversionError::new("unknown field").with_tag(10));
+
+    let mut map = BTreeMap::new();
+    map.insert(2, DynValue::Int(-1));
+    let error = Record::from_dynamic(map).unwrap_err();
+    assert_eq!(error.tag_path(), &[2]);
+
+    // The path of an error in a nested message starts with the innermost field.
+    let mut child = BTreeMap::new();
+    child.insert(4, DynValue::Bytes(vec![]));
+    let mut map = BTreeMap::new();
+    map.insert(8, DynValue::List(vec![DynValue::Message(child)]));
+    let error = Record::from_dynamic(map).unwrap_err();
+    assert_eq!(error.tag_path(), &[4, 8]);
+}
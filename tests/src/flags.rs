@@ -0,0 +1,178 @@
+//! Tests for encoding `bool` fields as the bits of a single flags field.
+
+use prost::alloc::format;
+#[cfg(not(feature = "std"))]
+use prost::alloc::string::String;
+use prost::{Message, MessageSchema};
+
+use crate::check_message;
+
+/// Feature switches, encoded as the bits of field 2.
+#[derive(Clone, PartialEq, Message)]
+#[prost(flags(tag = "2"), descriptor, field_sizes)]
+struct Flags {
+    #[prost(uint32, tag = "1")]
+    id: u32,
+    #[prost(flag = "0")]
+    a: bool,
+    #[prost(flag = "1")]
+    b: bool,
+    #[prost(flag = "2")]
+    c: bool,
+    #[prost(flag = "3")]
+    d: bool,
+    #[prost(flag = "4")]
+    e: bool,
+    #[prost(flag = "5")]
+    f: bool,
+    #[prost(flag = "63")]
+    last: bool,
+    #[prost(string, tag = "3")]
+    name: String,
+}
+
+/// The same switches, each encoded as its own field.
+#[derive(Clone, PartialEq, Message)]
+struct Bools {
+    #[prost(uint32, tag = "1")]
+    id: u32,
+    #[prost(bool, tag = "4")]
+    a: bool,
+    #[prost(bool, tag = "5")]
+    b: bool,
+    #[prost(bool, tag = "6")]
+    c: bool,
+    #[prost(bool, tag = "7")]
+    d: bool,
+    #[prost(bool, tag = "8")]
+    e: bool,
+    #[prost(bool, tag = "9")]
+    f: bool,
+    #[prost(bool, tag = "10")]
+    last: bool,
+    #[prost(string, tag = "3")]
+    name: String,
+}
+
+#[test]
+fn flags_roundtrip() {
+    let flags = Flags {
+        id: 7,
+        a: true,
+        c: true,
+        f: true,
+        name: "x".into(),
+        ..Flags::default()
+    };
+    // The flags are encoded in tag order, between fields 1 and 3.
+    assert_eq!(flags.encode_to_vec(), b"\x08\x07\x10\x25\x1a\x01x");
+    check_message(&flags);
+    assert_eq!(
+        format!("{:?}", flags),
+        "Flags { id: 7, a: true, b: false, c: true, d: false, e: false, f: true, last: false, \
+         name: \"x\" }"
+    );
+
+    let last = Flags {
+        last: true,
+        ..Flags::default()
+    };
+    check_message(&last);
+    assert_eq!(last.encoded_len(), 11);
+
+    // No flags set encodes nothing, and clearing resets every flag.
+    assert_eq!(Flags::default().encoded_len(), 0);
+    let mut cleared = flags.clone();
+    cleared.clear();
+    assert_eq!(cleared, Flags::default());
+
+    // The last value of the flags field wins, as for any scalar field.
+    let merged = Flags::decode(&b"\x10\x03\x10\x04"[..]).unwrap();
+    assert!(!merged.a && !merged.b && merged.c);
+
+    assert_eq!(Flags::MAX_ENCODED_LEN, None);
+    assert_ne!(Flags::SCHEMA_FINGERPRINT, Bools::SCHEMA_FINGERPRINT);
+}
+
+#[test]
+fn flags_are_smaller_than_bools() {
+    let flags = Flags {
+        a: true,
+        b: true,
+        c: true,
+        d: true,
+        e: true,
+        f: true,
+        ..Flags::default()
+    };
+    let bools = Bools {
+        a: true,
+        b: true,
+        c: true,
+        d: true,
+        e: true,
+        f: true,
+        ..Bools::default()
+    };
+    assert_eq!(flags.encoded_len(), 2);
+    assert_eq!(bools.encoded_len(), 12);
+}
+
+#[test]
+fn flags_field_size() {
+    let flags = Flags {
+        id: 7,
+        b: true,
+        name: "n".into(),
+        ..Flags::default()
+    };
+    assert_eq!(flags.field_sizes(), [(1, 2), (2, 2), (3, 3)]);
+    assert_eq!(Flags::default().field_sizes(), []);
+}
+
+#[test]
+fn flags_descriptor() {
+    let descriptor = Flags::descriptor();
+    let names = descriptor
+        .fields
+        .iter()
+        .map(|field| (field.tag, field.name))
+        .collect::<prost::alloc::vec::Vec<_>>();
+    assert_eq!(names, [(1, "id"), (2, "flags"), (3, "name")]);
+}
+
+#[test]
+fn flags_with_other_attributes() {
+    #[derive(Clone, PartialEq, Message)]
+    #[prost(flags(tag = "2"), declaration_order)]
+    struct Legacy {
+        #[prost(string, tag = "3")]
+        name: String,
+        #[prost(uint32, tag = "1")]
+        id: u32,
+        #[prost(flag = "0")]
+        a: bool,
+    }
+
+    // Both attributes take effect when they share a `#[prost(...)]`.
+    let legacy = Legacy {
+        name: "n".into(),
+        id: 7,
+        a: true,
+    };
+    assert_eq!(legacy.encode_to_vec(), b"\x1a\x01n\x08\x07\x10\x01");
+    check_message(&legacy);
+
+    // The encoding order doesn't change the schema.
+    #[derive(Clone, PartialEq, Message)]
+    #[prost(flags(tag = "2"))]
+    struct Tagged {
+        #[prost(string, tag = "3")]
+        name: String,
+        #[prost(uint32, tag = "1")]
+        id: u32,
+        #[prost(flag = "0")]
+        a: bool,
+    }
+    assert_eq!(Legacy::SCHEMA_FINGERPRINT, Tagged::SCHEMA_FINGERPRINT);
+}
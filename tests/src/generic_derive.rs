@@ -26,3 +26,46 @@ fn generic_enum() {
         GenericEnum::Number(_) => panic!("Not supposed to reach"),
     }
 }
+
+/// A marker type which has no encoding, and no `Default` implementation.
+#[derive(Clone, Debug, PartialEq)]
+struct Meters;
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct Measurement<U: core::fmt::Debug + Send + Sync> {
+    #[prost(double, tag = "1")]
+    value: f64,
+    unit: core::marker::PhantomData<U>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+struct TupleMeasurement<U: core::fmt::Debug + Send + Sync>(
+    #[prost(double, tag = "1")] f64,
+    core::marker::PhantomData<U>,
+    #[prost(string, tag = "2")] prost::alloc::string::String,
+);
+
+#[test]
+fn phantom_data_field() {
+    use prost::{Message, MessageSchema};
+
+    let measurement = Measurement::<Meters> {
+        value: 1.5,
+        unit: core::marker::PhantomData,
+    };
+    let encoded = measurement.encode_to_vec();
+    assert_eq!(encoded, b"\x09\x00\x00\x00\x00\x00\x00\xf8\x3f");
+    assert_eq!(
+        Measurement::<Meters>::decode(encoded.as_slice()).unwrap(),
+        measurement
+    );
+    assert_eq!(Measurement::<Meters>::MAX_ENCODED_LEN, Some(9));
+
+    let tuple = TupleMeasurement::<Meters>(1.5, core::marker::PhantomData, "m".into());
+    let decoded = TupleMeasurement::<Meters>::decode(tuple.encode_to_vec().as_slice()).unwrap();
+    assert_eq!(decoded, tuple);
+
+    let mut tuple = tuple;
+    tuple.clear();
+    assert_eq!(tuple, TupleMeasurement::default());
+}
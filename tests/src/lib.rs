@@ -37,8 +37,12 @@ mod debug;
 #[cfg(test)]
 mod deprecated_field;
 #[cfg(test)]
+mod dynamic;
+#[cfg(test)]
 mod enum_keyword_variant;
 #[cfg(test)]
+mod flags;
+#[cfg(test)]
 mod generic_derive;
 #[cfg(test)]
 mod message_encoding;
@@ -51,6 +55,8 @@ mod skip_debug;
 mod submessage_without_package;
 #[cfg(test)]
 mod type_names;
+#[cfg(test)]
+mod validate;
 
 mod test_enum_named_option_value {
     include!(concat!(env!("OUT_DIR"), "/myenum.optionn.rs"));
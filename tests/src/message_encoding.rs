@@ -3,13 +3,14 @@ use prost::alloc::vec;
 use prost::alloc::{borrow::ToOwned, string::String, vec::Vec};
 
 use prost::bytes::Bytes;
-use prost::{Enumeration, Message, Oneof};
+use prost::{Enumeration, Message, MessageSchema, Oneof};
 
 use crate::check_message;
 use crate::check_serialize_equivalent;
 
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, Message)]
+#[prost(descriptor)]
 pub struct RepeatedFloats {
     #[prost(float, tag = "11")]
     pub single_float: f32,
@@ -325,6 +326,1064 @@ fn check_default_values() {
     assert_eq!(0, default.encoded_len());
 }
 
+/// A prost message whose fields all have a bounded encoded length.
+#[allow(clippy::derive_partial_eq_without_eq)]
+#[derive(Clone, PartialEq, Message)]
+pub struct BoundedLength {
+    #[prost(int32, tag = "1")]
+    pub int32: i32,
+    #[prost(sint32, tag = "2")]
+    pub sint32: i32,
+    #[prost(uint64, optional, tag = "3")]
+    pub optional_uint64: Option<u64>,
+    #[prost(fixed32, required, tag = "4")]
+    pub required_fixed32: u32,
+    #[prost(double, tag = "5")]
+    pub double: f64,
+    #[prost(bool, tag = "16")]
+    pub bool: bool,
+    #[prost(enumeration = "BasicEnumeration", tag = "2048")]
+    pub enumeration: i32,
+}
+
+#[test]
+fn check_max_encoded_len() {
+    let max = BoundedLength::MAX_ENCODED_LEN.unwrap();
+    assert_eq!(max, 11 + 6 + 11 + 5 + 9 + 3 + 13);
+
+    let values = [
+        BoundedLength::default(),
+        BoundedLength {
+            int32: 1,
+            sint32: -1,
+            optional_uint64: Some(0),
+            required_fixed32: 7,
+            double: 1.5,
+            bool: true,
+            enumeration: BasicEnumeration::THREE as i32,
+        },
+        BoundedLength {
+            int32: -1,
+            sint32: i32::MIN,
+            optional_uint64: Some(u64::MAX),
+            required_fixed32: u32::MAX,
+            double: f64::MAX,
+            bool: true,
+            enumeration: i32::MIN,
+        },
+    ];
+    for value in &values {
+        assert!(value.encode_to_vec().len() <= max);
+        check_message(value);
+    }
+    assert_eq!(values[2].encode_to_vec().len(), max);
+
+    assert_eq!(Basic::MAX_ENCODED_LEN, None);
+    assert_eq!(Compound::MAX_ENCODED_LEN, None);
+}
+
+#[test]
+fn check_clear_retains_capacity() {
+    let mut message = Compound {
+        repeated_message: vec![Basic::default(); 16],
+        required_message: Basic {
+            bools: vec![true; 64],
+            string: "a string which needs an allocation".to_owned(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let repeated_capacity = message.repeated_message.capacity();
+    let bools_capacity = message.required_message.bools.capacity();
+    let string_capacity = message.required_message.string.capacity();
+
+    message.clear();
+    assert_eq!(message, Compound::default());
+    assert_eq!(message.repeated_message.capacity(), repeated_capacity);
+    assert_eq!(message.required_message.bools.capacity(), bools_capacity);
+    assert_eq!(message.required_message.string.capacity(), string_capacity);
+
+    // Decoding into a cleared message reuses its allocations.
+    let source = Compound {
+        repeated_message: vec![Basic::default(); 4],
+        ..Default::default()
+    };
+    message.merge(source.encode_to_vec().as_slice()).unwrap();
+    assert_eq!(message, source);
+    assert_eq!(message.repeated_message.capacity(), repeated_capacity);
+}
+
+#[test]
+fn check_decode_partial() {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct Record {
+        #[prost(uint64, tag = "1")]
+        sequence: u64,
+        #[prost(string, tag = "2")]
+        name: String,
+        #[prost(message, optional, tag = "3")]
+        nested: Option<Basic>,
+        #[prost(string, repeated, tag = "4")]
+        labels: Vec<String>,
+    }
+
+    let record = Record {
+        sequence: 42,
+        name: "first".to_owned(),
+        nested: Some(Basic {
+            int32: 7,
+            string: "nested".to_owned(),
+            ..Default::default()
+        }),
+        labels: vec!["a".to_owned(), "b".to_owned()],
+    };
+    let encoded = record.encode_to_vec();
+
+    let (decoded, error) = Record::decode_partial(encoded.as_slice());
+    assert_eq!(decoded, record);
+    assert!(error.is_none());
+
+    // Truncating partway through the nested message keeps the fields before it, and leaves out
+    // the nested message instead of returning it half decoded.
+    let nested_end = encoded.len() - 6;
+    let (decoded, error) = Record::decode_partial(&encoded[..nested_end - 3]);
+    assert!(error.is_some());
+    assert_eq!(
+        decoded,
+        Record {
+            sequence: 42,
+            name: "first".to_owned(),
+            ..Default::default()
+        }
+    );
+
+    // Truncating the last label keeps the first.
+    let (decoded, error) = Record::decode_partial(&encoded[..encoded.len() - 1]);
+    assert!(error.is_some());
+    assert_eq!(decoded.nested, record.nested);
+    assert_eq!(decoded.labels, ["a"]);
+}
+
+#[test]
+fn check_decode_error_field_context() {
+    use prost::WireType;
+
+    #[derive(Clone, PartialEq, Eq, Message)]
+    struct Inner {
+        #[prost(uint32, tag = "3")]
+        value: u32,
+    }
+
+    #[derive(Clone, PartialEq, Eq, Message)]
+    struct Outer {
+        #[prost(message, optional, tag = "1")]
+        inner: Option<Inner>,
+    }
+
+    // The nested field is encoded as a fixed64 instead of a varint.
+    let encoded = [0x0a, 0x09, 0x19, 0, 0, 0, 0, 0, 0, 0, 0];
+    let error = Outer::decode(encoded.as_slice()).unwrap_err();
+    assert_eq!(error.field_context(), Some((3, WireType::SixtyFourBit)));
+    assert!(error.to_string().contains("Inner.value: Outer.inner: "));
+
+    // The outer field is encoded as a varint instead of a length-delimited message.
+    let error = Outer::decode([0x08, 0x01].as_slice()).unwrap_err();
+    assert_eq!(error.field_context(), Some((1, WireType::Varint)));
+
+    // Errors outside of any field have no field context.
+    let error = Outer::decode([0x80].as_slice()).unwrap_err();
+    assert_eq!(error.field_context(), None);
+}
+
+#[test]
+fn check_tag_window() {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    #[prost(min_tag = "100", max_tag = "199")]
+    struct Plugin {
+        #[prost(uint32, tag = "100")]
+        version: u32,
+        #[prost(string)]
+        name: String,
+        #[prost(oneof = "PluginConfig", tags = "150, 199")]
+        config: Option<PluginConfig>,
+    }
+
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Oneof)]
+    enum PluginConfig {
+        #[prost(bytes, tag = "150")]
+        Raw(Vec<u8>),
+        #[prost(string, tag = "199")]
+        Path(String),
+    }
+
+    // The window restricts the tags which may be declared, without changing the encoding.
+    let plugin = Plugin {
+        version: 1,
+        name: "plugin".to_owned(),
+        config: Some(PluginConfig::Path("/etc/plugin".to_owned())),
+    };
+    check_message(&plugin);
+}
+
+#[test]
+fn check_reserved_tags() {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    #[prost(reserved_tags = "2, 5..=7")]
+    struct Account {
+        #[prost(uint32, tag = "1")]
+        id: u32,
+        #[prost(string, tag = "3")]
+        name: String,
+        #[prost(int32, repeated, tag = "8")]
+        scores: Vec<i32>,
+    }
+
+    assert_eq!(Account::RESERVED_TAGS, [2..=2, 5..=7]);
+    assert!(Basic::RESERVED_TAGS.is_empty());
+
+    let account = Account {
+        id: 1,
+        name: "account".to_owned(),
+        scores: vec![3, 4],
+    };
+    let mut encoded = account.encode_to_vec();
+    assert_eq!(
+        prost::uses_reserved_tags(encoded.as_slice(), Account::RESERVED_TAGS).unwrap(),
+        []
+    );
+
+    // Data written before the tags were retired still carries them.
+    prost::encoding::uint32::encode(6, &9, &mut encoded);
+    prost::encoding::string::encode(2, &"old".to_owned(), &mut encoded);
+    prost::encoding::uint32::encode(6, &10, &mut encoded);
+    assert_eq!(
+        prost::uses_reserved_tags(encoded.as_slice(), Account::RESERVED_TAGS).unwrap(),
+        [2, 6]
+    );
+    assert_eq!(Account::decode(encoded.as_slice()).unwrap(), account);
+
+    encoded.push(0x08);
+    assert!(prost::uses_reserved_tags(encoded.as_slice(), Account::RESERVED_TAGS).is_err());
+}
+
+#[test]
+fn check_optional_message_presence() {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct Inner {
+        #[prost(uint32, tag = "1")]
+        value: u32,
+    }
+
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct Outer {
+        #[prost(message, optional, tag = "1")]
+        inner: Option<Inner>,
+    }
+
+    // An absent message is omitted, while a present but empty message is encoded with a zero
+    // length, so the two decode differently.
+    let cases: &[(Option<Inner>, &[u8])] = &[
+        (None, b""),
+        (Some(Inner::default()), b"\x0a\x00"),
+        (Some(Inner { value: 3 }), b"\x0a\x02\x08\x03"),
+    ];
+    for (inner, encoded) in cases {
+        let outer = Outer {
+            inner: inner.clone(),
+        };
+        assert_eq!(outer.encode_to_vec(), *encoded);
+        assert_eq!(Outer::decode(*encoded).unwrap(), outer);
+        check_message(&outer);
+    }
+}
+
+#[test]
+fn check_max_tag() {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct Sparse {
+        #[prost(uint32, tag = "1000")]
+        high: u32,
+        #[prost(string, tag = "2")]
+        low: String,
+    }
+
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct WithOneof {
+        #[prost(uint32, tag = "1")]
+        id: u32,
+        #[prost(oneof = "SparseOneof", tags = "3, 536870911")]
+        value: Option<SparseOneof>,
+    }
+
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Oneof)]
+    enum SparseOneof {
+        #[prost(uint32, tag = "3")]
+        Low(u32),
+        #[prost(bool, tag = "536870911")]
+        Max(bool),
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    struct Empty {}
+
+    assert_eq!(Sparse::MAX_TAG, 1000);
+    assert_eq!(WithOneof::MAX_TAG, 536_870_911);
+    assert_eq!(Empty::MAX_TAG, 0);
+    assert_eq!(Basic::MAX_TAG, 12);
+}
+
+#[test]
+fn check_mixed_packed_and_unpacked() {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct Repeated {
+        #[prost(uint32, repeated, tag = "1")]
+        packed: Vec<u32>,
+        #[prost(uint32, repeated, packed = "false", tag = "2")]
+        unpacked: Vec<u32>,
+    }
+
+    // Either field accepts packed and unpacked values, in any interleaving, collecting them in
+    // order.
+    let encoded =
+        b"\x0a\x02\x01\x02\x08\x03\x12\x01\x06\x0a\x01\x04\x10\x07\x08\x05\x12\x02\x08\x09";
+    let decoded = Repeated::decode(&encoded[..]).unwrap();
+    assert_eq!(
+        decoded,
+        Repeated {
+            packed: vec![1, 2, 3, 4, 5],
+            unpacked: vec![6, 7, 8, 9],
+        }
+    );
+
+    // Each field is encoded in the way it is declared.
+    assert_eq!(
+        decoded.encode_to_vec(),
+        b"\x0a\x05\x01\x02\x03\x04\x05\x10\x06\x10\x07\x10\x08\x10\x09"
+    );
+}
+
+// Basic only has its bytes_map field with std.
+#[cfg(feature = "std")]
+#[test]
+fn check_field_sizes() {
+    let message = Basic {
+        int32: 42,
+        bools: vec![true, false],
+        string: "a string".to_owned(),
+        oneof: Some(BasicOneof::Int(7)),
+        bytes_map: [("key".to_owned(), b"value".to_vec())]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+
+    let sizes = message.field_sizes();
+    assert_eq!(
+        sizes.iter().map(|&(tag, _)| tag).collect::<Vec<_>>(),
+        [1, 2, 3, 8, 12]
+    );
+    assert_eq!(sizes[0], (1, 2));
+    assert_eq!(sizes[1], (2, 4));
+    assert_eq!(
+        sizes.iter().map(|&(_, len)| len).sum::<usize>(),
+        message.encoded_len()
+    );
+
+    assert!(Basic::default().field_sizes().is_empty());
+}
+
+#[test]
+fn check_descriptor() {
+    use prost::encoding::WireType;
+    use prost::FieldDescriptor;
+
+    let descriptor = Basic::descriptor();
+    assert_eq!(descriptor.name, "Basic");
+    assert_eq!(
+        descriptor
+            .fields
+            .iter()
+            .map(|field| field.tag)
+            .collect::<Vec<_>>(),
+        [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]
+    );
+    assert_eq!(
+        descriptor.fields[..3],
+        [
+            FieldDescriptor {
+                tag: 1,
+                wire_type: WireType::Varint,
+                name: "int32",
+                is_repeated: false,
+                is_oneof: false,
+            },
+            FieldDescriptor {
+                tag: 2,
+                wire_type: WireType::Varint,
+                name: "bools",
+                is_repeated: true,
+                is_oneof: false,
+            },
+            FieldDescriptor {
+                tag: 3,
+                wire_type: WireType::LengthDelimited,
+                name: "string",
+                is_repeated: false,
+                is_oneof: false,
+            },
+        ]
+    );
+    assert_eq!(
+        descriptor.field(9),
+        Some(&FieldDescriptor {
+            tag: 9,
+            wire_type: WireType::LengthDelimited,
+            name: "String",
+            is_repeated: false,
+            is_oneof: true,
+        })
+    );
+    assert_eq!(descriptor.field(9), BasicOneof::FIELD_DESCRIPTORS.get(1));
+    let map = descriptor.field(10).unwrap();
+    assert_eq!(map.name, "enumeration_btree_map");
+    assert!(map.is_repeated);
+    assert_eq!(descriptor.field(13), None);
+
+    assert_eq!(
+        RepeatedFloats::descriptor().field(41).unwrap().wire_type,
+        WireType::LengthDelimited
+    );
+
+    // Without the attribute there's no descriptor function, so a field named `descriptor` can
+    // have its usual getter.
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct Undescribed {
+        #[prost(enumeration = "BasicEnumeration", tag = "1")]
+        descriptor: i32,
+    }
+    let undescribed = Undescribed {
+        descriptor: BasicEnumeration::TWO as i32,
+    };
+    assert_eq!(undescribed.descriptor(), BasicEnumeration::TWO);
+}
+
+#[test]
+fn check_reverse_keys() {
+    use core::cmp::Reverse;
+    use prost::alloc::collections::BTreeMap;
+    use prost::alloc::format;
+
+    #[derive(Clone, PartialEq, Message)]
+    struct Descending {
+        #[prost(btree_map = "uint32, string", reverse_keys, tag = "1")]
+        names: BTreeMap<Reverse<u32>, String>,
+        #[prost(
+            btree_map = "string, enumeration(BasicEnumeration)",
+            reverse_keys,
+            tag = "2"
+        )]
+        enumerations: BTreeMap<Reverse<String>, i32>,
+    }
+
+    let mut message = Descending::default();
+    message.names.insert(Reverse(1), "one".to_owned());
+    message.names.insert(Reverse(2), "two".to_owned());
+    message.insert_enumerations("a".to_owned(), BasicEnumeration::TWO);
+
+    // Entries are encoded in descending key order, with the keys written as plain `uint32`s.
+    assert_eq!(
+        &message.encode_to_vec()[..13],
+        b"\x0a\x07\x08\x02\x12\x03two\x0a\x07\x08\x01"
+    );
+    check_message(&message);
+
+    assert_eq!(
+        message.get_enumerations("a".to_owned()),
+        Some(BasicEnumeration::TWO)
+    );
+    assert_eq!(
+        format!("{:?}", message),
+        "Descending { names: {2: \"two\", 1: \"one\"}, enumerations: {\"a\": TWO} }"
+    );
+}
+
+#[test]
+fn check_declaration_order() {
+    #[derive(Clone, PartialEq, Eq, Message)]
+    #[prost(declaration_order)]
+    struct Legacy {
+        #[prost(string, tag = "3")]
+        name: String,
+        #[prost(uint32, tag = "1")]
+        id: u32,
+        #[prost(bool, tag = "2")]
+        active: bool,
+    }
+
+    let message = Legacy {
+        name: "a".to_owned(),
+        id: 7,
+        active: true,
+    };
+
+    // Fields are encoded in the order they're declared in, not in tag order.
+    assert_eq!(message.encode_to_vec(), b"\x1a\x01a\x08\x07\x10\x01");
+    check_message(&message);
+
+    // Messages encoded in tag order still decode the same.
+    assert_eq!(
+        Legacy::decode(&b"\x08\x07\x10\x01\x1a\x01a"[..]).unwrap(),
+        message
+    );
+}
+
+#[test]
+fn check_optional_packed() {
+    use prost::alloc::format;
+
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct Patch {
+        #[prost(int32, optional, packed = "true", tag = "1")]
+        values: Option<Vec<i32>>,
+        #[prost(enumeration = "BasicEnumeration", optional, packed = "true", tag = "2")]
+        enumerations: Option<Vec<i32>>,
+    }
+
+    // An absent list is omitted entirely.
+    let absent = Patch::default();
+    assert_eq!(absent.encode_to_vec(), b"");
+    check_message(&absent);
+
+    // An empty list is written as a zero-length packed field.
+    let empty = Patch {
+        values: Some(vec![]),
+        enumerations: None,
+    };
+    assert_eq!(empty.encoded_len(), 2);
+    assert_eq!(empty.encode_to_vec(), b"\x0a\x00");
+    check_message(&empty);
+
+    let non_empty = Patch {
+        values: Some(vec![1, 2]),
+        enumerations: Some(vec![BasicEnumeration::TWO as i32]),
+    };
+    assert_eq!(non_empty.encode_to_vec(), b"\x0a\x02\x01\x02\x12\x01\x02");
+    check_message(&non_empty);
+    assert_eq!(
+        format!("{:?}", non_empty),
+        "Patch { values: Some([1, 2]), enumerations: Some([TWO]) }"
+    );
+
+    // Unpacked elements are accepted as well.
+    assert_eq!(
+        Patch::decode(&b"\x08\x01\x08\x02"[..]).unwrap(),
+        Patch {
+            values: Some(vec![1, 2]),
+            enumerations: None,
+        }
+    );
+}
+
+#[test]
+fn check_repeated_enumeration_unknown_values() {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct Enumerations {
+        #[prost(enumeration = "BasicEnumeration", repeated, tag = "1")]
+        packed: Vec<i32>,
+        #[prost(
+            enumeration = "BasicEnumeration",
+            repeated,
+            packed = "false",
+            tag = "2"
+        )]
+        unpacked: Vec<i32>,
+    }
+
+    // Enumerations are open, so an element which is not a known value is kept rather than
+    // failing the field, whichever way the field is encoded.
+    let decoded = Enumerations::decode(&b"\x0a\x02\x02\x07\x10\x02\x10\x07"[..]).unwrap();
+    assert_eq!(decoded.packed, [BasicEnumeration::TWO as i32, 7]);
+    assert_eq!(decoded.unpacked, [BasicEnumeration::TWO as i32, 7]);
+    check_message(&decoded);
+
+    // The typed accessors skip the unknown values.
+    assert_eq!(
+        decoded.packed().collect::<Vec<_>>(),
+        [BasicEnumeration::TWO]
+    );
+    assert_eq!(
+        decoded.unpacked().collect::<Vec<_>>(),
+        [BasicEnumeration::TWO]
+    );
+}
+
+#[cfg(all(unix, feature = "std"))]
+#[test]
+fn check_os_string() {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+    use std::path::PathBuf;
+
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct Entry {
+        #[prost(bytes = "vec", tag = "1")]
+        path: OsString,
+        #[prost(bytes = "vec", repeated, tag = "2")]
+        aliases: Vec<OsString>,
+        #[prost(bytes = "vec", tag = "3")]
+        config: PathBuf,
+    }
+
+    let entry = Entry {
+        path: OsString::from_vec(b"/srv/\xff\xfe".to_vec()),
+        aliases: vec![OsString::from("alias"), OsString::new()],
+        config: PathBuf::from(OsString::from_vec(b"\xff.toml".to_vec())),
+    };
+    check_message(&entry);
+    assert_eq!(entry.encoded_len(), 9 + 7 + 2 + 8);
+
+    check_message(&Entry::default());
+    assert_eq!(Entry::default().encoded_len(), 0);
+}
+
+#[test]
+fn check_fixed_size_bytes() {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct Block {
+        #[prost(bytes = "vec", tag = "1")]
+        hash: [u8; 32],
+        #[prost(bytes = "vec", optional, tag = "2")]
+        parent: Option<[u8; 32]>,
+        #[prost(bytes = "vec", repeated, tag = "3")]
+        children: Vec<[u8; 4]>,
+        // Arrays longer than 32 bytes have no `Default` implementation.
+        #[prost(bytes = "vec", tag = "4")]
+        signature: [u8; 64],
+        #[prost(bytes = "vec", optional, tag = "5")]
+        witness: Option<[u8; 64]>,
+    }
+
+    // The all-zero array is the empty state, so it is not encoded.
+    let empty = Block::default();
+    assert_eq!(empty.encoded_len(), 0);
+    check_message(&empty);
+
+    let block = Block {
+        hash: [1; 32],
+        parent: Some([0; 32]),
+        children: vec![[2; 4], [0; 4]],
+        signature: [3; 64],
+        witness: Some([4; 64]),
+    };
+    assert_eq!(block.encoded_len(), 34 + 34 + 6 + 6 + 66 + 66);
+    check_message(&block);
+
+    let mut cleared = block.clone();
+    cleared.clear();
+    assert_eq!(cleared, Block::default());
+
+    // Values of any other length are rejected.
+    assert!(Block::decode(&b"\x0a\x03abc"[..]).is_err());
+    assert!(Block::decode(&b"\x1a\x05abcde"[..]).is_err());
+    assert!(Block::decode(&b"\x22\x03abc"[..]).is_err());
+}
+
+#[test]
+fn check_correlation_id() {
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct Request {
+        #[prost(uint32, tag = "1")]
+        attempt: u32,
+        #[prost(string, tag = "2", id)]
+        request_id: String,
+    }
+
+    let request = Request {
+        attempt: 2,
+        request_id: "req-7".to_owned(),
+    };
+    assert_eq!(request.correlation_id(), "req-7");
+
+    // Marking a field as the id does not change its encoding.
+    check_message(&request);
+    assert_eq!(request.encode_to_vec(), b"\x08\x02\x12\x05req-7");
+}
+
+#[test]
+fn check_box_str() {
+    use prost::alloc::boxed::Box;
+
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct Names {
+        #[prost(string, tag = "1")]
+        name: Box<str>,
+        #[prost(string, optional, tag = "2")]
+        nickname: Option<Box<str>>,
+        #[prost(string, repeated, tag = "3")]
+        aliases: Vec<Box<str>>,
+        #[prost(string, tag = "4", default = "unknown")]
+        title: Box<str>,
+    }
+
+    // The empty string is the empty state, so it is not encoded.
+    let default = Names::default();
+    assert_eq!(&*default.name, "");
+    assert_eq!(&*default.title, "unknown");
+    assert_eq!(default.nickname(), "");
+    assert_eq!(default.encoded_len(), 0);
+
+    let names = Names {
+        name: "Ferris".into(),
+        nickname: Some("".into()),
+        aliases: vec!["crab".into(), "".into()],
+        title: "".into(),
+    };
+    check_message(&names);
+    let mut cleared = names.clone();
+    cleared.clear();
+    assert_eq!(&*cleared.name, "");
+
+    // Invalid UTF-8 is rejected.
+    assert!(Names::decode(&b"\x0a\x02\xc3\x28"[..]).is_err());
+    assert!(Names::decode(&b"\x1a\x01\xff"[..]).is_err());
+}
+
+#[test]
+fn check_encode_filtered() {
+    use prost::alloc::collections::BTreeSet;
+
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct Account {
+        #[prost(uint64, tag = "1")]
+        id: u64,
+        #[prost(string, tag = "2")]
+        password: String,
+        #[prost(message, repeated, tag = "3")]
+        history: Vec<Basic>,
+        #[prost(group, optional, tag = "4")]
+        settings: Option<Basic>,
+    }
+
+    let account = Account {
+        id: 7,
+        password: "hunter2".to_owned(),
+        history: vec![Basic::default(); 2],
+        settings: Some(Basic {
+            string: "dark".to_owned(),
+            ..Basic::default()
+        }),
+    };
+    assert_eq!(
+        account.encode_filtered_to_vec(&BTreeSet::new()).unwrap(),
+        account.encode_to_vec()
+    );
+
+    let redacted = account
+        .encode_filtered_to_vec(&BTreeSet::from([2]))
+        .unwrap();
+    assert_eq!(
+        Account::decode(redacted.as_slice()).unwrap(),
+        Account {
+            password: String::new(),
+            ..account.clone()
+        }
+    );
+
+    // Every occurrence of a repeated field is omitted, as are groups.
+    let redacted = account
+        .encode_filtered_to_vec(&BTreeSet::from([3, 4, 5]))
+        .unwrap();
+    assert_eq!(
+        Account::decode(redacted.as_slice()).unwrap(),
+        Account {
+            history: vec![],
+            settings: None,
+            ..account
+        }
+    );
+
+    // Groups nested more deeply than decoding allows are still encoded.
+    #[derive(Clone, PartialEq, Message)]
+    struct Nested {
+        #[prost(group, repeated, tag = "1")]
+        inner: Vec<Nested>,
+        #[prost(uint32, tag = "2")]
+        depth: u32,
+    }
+
+    let mut nested = Nested::default();
+    for depth in 1..=150 {
+        nested = Nested {
+            inner: vec![nested],
+            depth,
+        };
+    }
+    let mut expected = nested.clone();
+    expected.depth = 0;
+    assert_eq!(
+        nested.encode_filtered_to_vec(&BTreeSet::from([2])).unwrap(),
+        expected.encode_to_vec()
+    );
+}
+
+#[test]
+fn check_cow_slice() {
+    use prost::alloc::borrow::Cow;
+
+    #[allow(clippy::derive_partial_eq_without_eq)]
+    #[derive(Clone, PartialEq, Message)]
+    struct Batch<'a> {
+        #[prost(sint32, repeated, tag = "1")]
+        values: Cow<'a, [i32]>,
+        #[prost(string, repeated, tag = "2")]
+        labels: Cow<'a, [String]>,
+        #[prost(message, repeated, tag = "3")]
+        children: Cow<'a, [Basic]>,
+        #[prost(enumeration = "BasicEnumeration", repeated, tag = "4")]
+        kinds: Cow<'a, [i32]>,
+    }
+
+    let values = [1, -2, 3];
+    let labels = ["a".to_owned(), "b".to_owned()];
+    let children = [Basic {
+        int32: 4,
+        ..Basic::default()
+    }];
+    let borrowed = Batch {
+        values: Cow::Borrowed(&values),
+        labels: Cow::Borrowed(&labels),
+        children: Cow::Borrowed(&children),
+        kinds: Cow::Borrowed(&[]),
+    };
+    let owned = Batch {
+        values: Cow::Owned(values.to_vec()),
+        labels: Cow::Owned(labels.to_vec()),
+        children: Cow::Owned(children.to_vec()),
+        kinds: Cow::Owned(vec![]),
+    };
+    let encoded = borrowed.encode_to_vec();
+    assert_eq!(encoded, owned.encode_to_vec());
+    assert_eq!(borrowed.encoded_len(), encoded.len());
+
+    let decoded = Batch::decode(encoded.as_slice()).unwrap();
+    assert!(matches!(decoded.values, Cow::Owned(_)));
+    assert!(matches!(decoded.labels, Cow::Owned(_)));
+    assert!(matches!(decoded.children, Cow::Owned(_)));
+    assert_eq!(decoded, borrowed);
+
+    // Merging into a borrowed slice appends to a copy of it.
+    let mut merged = borrowed.clone();
+    merged.merge(&b"\x08\x0a"[..]).unwrap();
+    assert_eq!(&*merged.values, &[1, -2, 3, 5]);
+    merged.push_kinds(BasicEnumeration::TWO);
+    assert_eq!(merged.kinds().collect::<Vec<_>>(), [BasicEnumeration::TWO]);
+
+    let mut cleared = borrowed;
+    cleared.clear();
+    assert_eq!(cleared, Batch::default());
+}
+
+#[test]
+fn check_enumeration_variants() {
+    assert_eq!(
+        BasicEnumeration::all_variants(),
+        [
+            BasicEnumeration::ZERO,
+            BasicEnumeration::ONE,
+            BasicEnumeration::TWO,
+            BasicEnumeration::THREE,
+        ]
+    );
+    assert_eq!(BasicEnumeration::all_numbers(), [0, 1, 2, 3]);
+    for (&variant, &number) in BasicEnumeration::all_variants()
+        .iter()
+        .zip(BasicEnumeration::all_numbers())
+    {
+        assert_eq!(i32::from(variant), number);
+    }
+}
+
+#[test]
+fn check_enumeration_is_valid() {
+    #[derive(Clone, Copy, Debug, PartialEq, Enumeration)]
+    enum Sparse {
+        Zero = 0,
+        Five = 5,
+        Last = 63,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Enumeration)]
+    enum Wide {
+        Small = 1,
+        Large = 64,
+        Negative = -1,
+        Max = 0x7fff_ffff,
+    }
+
+    // Small literal values are checked with a bitmask, and other values with a match; both must
+    // agree with the declared values.
+    fn check(is_valid: fn(i32) -> bool, numbers: &[i32]) {
+        let samples = (-200..200)
+            .chain([i32::MIN, i32::MIN + 1, i32::MAX - 1, i32::MAX])
+            .chain((0..32).map(|shift| 1 << shift));
+        for value in samples {
+            assert_eq!(is_valid(value), numbers.contains(&value), "value {}", value);
+        }
+    }
+    check(Sparse::is_valid, Sparse::all_numbers());
+    check(Wide::is_valid, Wide::all_numbers());
+    check(BasicEnumeration::is_valid, BasicEnumeration::all_numbers());
+}
+
+mod fingerprint {
+    use super::*;
+
+    #[derive(Clone, PartialEq, Message)]
+    #[prost(framed)]
+    pub struct V1 {
+        #[prost(uint32, tag = "1")]
+        pub id: u32,
+        #[prost(string, tag = "2")]
+        pub name: String,
+        #[prost(oneof = "V1Oneof", tags = "3, 4")]
+        pub oneof: Option<V1Oneof>,
+    }
+
+    #[derive(Clone, PartialEq, Oneof)]
+    pub enum V1Oneof {
+        #[prost(int32, tag = "3")]
+        Int(i32),
+        #[prost(string, tag = "4")]
+        Name(String),
+    }
+
+    /// Renamed fields and a type change which keeps the wire type.
+    #[derive(Clone, PartialEq, Message)]
+    #[prost(framed)]
+    pub struct Renamed {
+        #[prost(int64, tag = "1")]
+        pub key: i64,
+        #[prost(bytes = "vec", tag = "2")]
+        pub label: Vec<u8>,
+        #[prost(oneof = "RenamedOneof", tags = "3, 4")]
+        pub choice: Option<RenamedOneof>,
+    }
+
+    #[derive(Clone, PartialEq, Oneof)]
+    pub enum RenamedOneof {
+        #[prost(string, tag = "4")]
+        Name(String),
+        #[prost(bool, tag = "3")]
+        Flag(bool),
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    #[prost(framed)]
+    pub struct FixedId {
+        #[prost(fixed32, tag = "1")]
+        pub id: u32,
+        #[prost(string, tag = "2")]
+        pub name: String,
+        #[prost(oneof = "V1Oneof", tags = "3, 4")]
+        pub oneof: Option<V1Oneof>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct Retagged {
+        #[prost(uint32, tag = "1")]
+        pub id: u32,
+        #[prost(string, tag = "5")]
+        pub name: String,
+        #[prost(oneof = "V1Oneof", tags = "3, 4")]
+        pub oneof: Option<V1Oneof>,
+    }
+
+    #[derive(Clone, PartialEq, Message)]
+    pub struct FixedOneof {
+        #[prost(uint32, tag = "1")]
+        pub id: u32,
+        #[prost(string, tag = "2")]
+        pub name: String,
+        #[prost(oneof = "FixedOneofOneof", tags = "3, 4")]
+        pub oneof: Option<FixedOneofOneof>,
+    }
+
+    #[derive(Clone, PartialEq, Oneof)]
+    pub enum FixedOneofOneof {
+        #[prost(sfixed32, tag = "3")]
+        Int(i32),
+        #[prost(string, tag = "4")]
+        Name(String),
+    }
+
+    #[test]
+    fn check_schema_fingerprint() {
+        assert_eq!(V1::SCHEMA_FINGERPRINT, Renamed::SCHEMA_FINGERPRINT);
+        assert_eq!(
+            V1Oneof::SCHEMA_FINGERPRINT,
+            RenamedOneof::SCHEMA_FINGERPRINT
+        );
+
+        assert_ne!(V1::SCHEMA_FINGERPRINT, FixedId::SCHEMA_FINGERPRINT);
+        assert_ne!(V1::SCHEMA_FINGERPRINT, Retagged::SCHEMA_FINGERPRINT);
+        assert_ne!(V1::SCHEMA_FINGERPRINT, FixedOneof::SCHEMA_FINGERPRINT);
+        assert_ne!(Basic::SCHEMA_FINGERPRINT, Compound::SCHEMA_FINGERPRINT);
+    }
+
+    #[test]
+    fn check_framed() {
+        let message = V1 {
+            id: 7,
+            name: "seven".to_owned(),
+            oneof: Some(V1Oneof::Int(-1)),
+        };
+        let framed = message.encode_framed();
+        assert_eq!(&framed[..4], b"PRST");
+        assert_eq!(&framed[4..12], V1::SCHEMA_FINGERPRINT.to_le_bytes());
+        assert_eq!(framed[12] as usize, message.encoded_len());
+        assert_eq!(&framed[13..], message.encode_to_vec());
+        assert_eq!(V1::decode_framed(framed.as_slice()).unwrap(), message);
+
+        // A schema with the same fingerprint decodes the message regardless of names.
+        let renamed = Renamed::decode_framed(framed.as_slice()).unwrap();
+        assert_eq!(renamed.key, 7);
+
+        // A schema with a different fingerprint is rejected.
+        assert_eq!(
+            FixedId::decode_framed(framed.as_slice()).unwrap_err(),
+            prost::DecodeError::new(prost::alloc::format!(
+                "schema fingerprint mismatch: {:#018x} (expected {:#018x})",
+                V1::SCHEMA_FINGERPRINT,
+                FixedId::SCHEMA_FINGERPRINT
+            ))
+        );
+
+        let mut bad_magic = framed.clone();
+        bad_magic[0] = b'X';
+        assert!(V1::decode_framed(bad_magic.as_slice()).is_err());
+        assert!(V1::decode_framed(&framed[..8]).is_err());
+        assert!(V1::decode_framed(&framed[..framed.len() - 1]).is_err());
+    }
+}
+
 /// A protobuf enum.
 #[allow(clippy::upper_case_acronyms, clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, Copy, Debug, PartialEq, Enumeration)]
@@ -337,6 +1396,7 @@ pub enum BasicEnumeration {
 
 #[allow(clippy::derive_partial_eq_without_eq)]
 #[derive(Clone, PartialEq, Message)]
+#[prost(descriptor, field_sizes)]
 pub struct Basic {
     #[prost(int32, tag = "1")]
     pub int32: i32,
@@ -0,0 +1,185 @@
+//! Tests for validating messages after they are decoded.
+
+use prost::alloc::vec;
+#[cfg(not(feature = "std"))]
+use prost::alloc::vec::Vec;
+use prost::{DecodeError, DecodeErrorKind, Message, Validate};
+
+#[derive(Clone, PartialEq, Message)]
+#[prost(validate)]
+struct Range {
+    #[prost(uint64, tag = "1")]
+    start: u64,
+    #[prost(uint64, tag = "2")]
+    end: u64,
+}
+
+impl Validate for Range {
+    fn validate(&self) -> Result<(), DecodeError> {
+        if self.start <= self.end {
+            Ok(())
+        } else {
+            Err(DecodeError::new("range start is after its end"))
+        }
+    }
+}
+
+/// Opts in to validation so that its nested ranges are checked, with no invariants of its own.
+#[derive(Clone, PartialEq, Message)]
+#[prost(validate)]
+struct Ranges {
+    #[prost(message, optional, tag = "1")]
+    single: Option<Range>,
+    #[prost(message, repeated, tag = "2")]
+    many: Vec<Range>,
+    #[prost(group, optional, tag = "3")]
+    group: Option<Range>,
+}
+
+impl Validate for Ranges {
+    fn validate(&self) -> Result<(), DecodeError> {
+        Ok(())
+    }
+}
+
+/// Doesn't opt in to validation, so its nested range isn't checked.
+#[derive(Clone, PartialEq, Message)]
+struct Unchecked {
+    #[prost(message, optional, tag = "1")]
+    single: Option<Range>,
+}
+
+#[test]
+fn validate_top_level() {
+    let valid = Range { start: 1, end: 2 };
+    assert_eq!(Range::decode(valid.encode_to_vec().as_slice()), Ok(valid));
+
+    let invalid = Range { start: 2, end: 1 };
+    let error = Range::decode(invalid.encode_to_vec().as_slice()).unwrap_err();
+    assert_eq!(error.kind(), DecodeErrorKind::ValidationFailed);
+    assert!(
+        Range::decode_length_delimited(invalid.encode_length_delimited_to_vec().as_slice())
+            .is_err()
+    );
+
+    // Fields merged on top of a valid message are checked as well.
+    let mut merged = Range { start: 1, end: 2 };
+    assert!(merged.merge(invalid.encode_to_vec().as_slice()).is_err());
+
+    // Malformed input is distinguished from input which fails validation.
+    let error = Range::decode(&b"\x08"[..]).unwrap_err();
+    assert_eq!(error.kind(), DecodeErrorKind::Malformed);
+}
+
+#[test]
+fn validate_nested() {
+    let valid = Range { start: 1, end: 2 };
+    let invalid = Range { start: 2, end: 1 };
+
+    let ranges = Ranges {
+        single: Some(valid.clone()),
+        many: vec![valid.clone(), valid.clone()],
+        group: Some(valid.clone()),
+    };
+    assert_eq!(
+        Ranges::decode(ranges.encode_to_vec().as_slice()),
+        Ok(ranges)
+    );
+
+    for ranges in [
+        Ranges {
+            single: Some(invalid.clone()),
+            ..Ranges::default()
+        },
+        Ranges {
+            many: vec![valid.clone(), invalid.clone()],
+            ..Ranges::default()
+        },
+        Ranges {
+            group: Some(invalid.clone()),
+            ..Ranges::default()
+        },
+    ] {
+        let error = Ranges::decode(ranges.encode_to_vec().as_slice()).unwrap_err();
+        assert_eq!(error.kind(), DecodeErrorKind::ValidationFailed);
+    }
+}
+
+#[test]
+fn validate_is_opt_in() {
+    let unchecked = Unchecked {
+        single: Some(Range { start: 2, end: 1 }),
+    };
+    assert_eq!(
+        Unchecked::decode(unchecked.encode_to_vec().as_slice()),
+        Ok(unchecked)
+    );
+}
+
+#[test]
+fn validate_after_merging_chunks() {
+    // A nested message may be split across several length-delimited chunks, which are merged
+    // together; it is only checked once the whole input has been decoded.
+    let mut encoded = Ranges {
+        single: Some(Range { start: 5, end: 0 }),
+        group: Some(Range { start: 5, end: 0 }),
+        ..Ranges::default()
+    }
+    .encode_to_vec();
+    Ranges {
+        single: Some(Range { start: 0, end: 10 }),
+        group: Some(Range { start: 0, end: 10 }),
+        ..Ranges::default()
+    }
+    .encode(&mut encoded)
+    .unwrap();
+
+    let expected = Ranges {
+        single: Some(Range { start: 5, end: 10 }),
+        group: Some(Range { start: 5, end: 10 }),
+        ..Ranges::default()
+    };
+    assert_eq!(Ranges::decode(encoded.as_slice()), Ok(expected.clone()));
+    let mut merged = Ranges::default();
+    merged.merge(encoded.as_slice()).unwrap();
+    assert_eq!(merged, expected);
+
+    // The error from a nested message which is still invalid once complete names its field.
+    let invalid = Ranges {
+        single: Some(Range { start: 5, end: 0 }),
+        ..Ranges::default()
+    };
+    let error = Ranges::decode(invalid.encode_to_vec().as_slice()).unwrap_err();
+    assert_eq!(error.kind(), DecodeErrorKind::ValidationFailed);
+    assert!(error.to_string().contains("Ranges.single"), "{}", error);
+}
+
+#[test]
+fn validate_with_other_attributes() {
+    #[derive(Clone, PartialEq, Message)]
+    #[prost(validate, declaration_order)]
+    struct Descending {
+        #[prost(uint64, tag = "2")]
+        end: u64,
+        #[prost(uint64, tag = "1")]
+        start: u64,
+    }
+
+    impl Validate for Descending {
+        fn validate(&self) -> Result<(), DecodeError> {
+            Range {
+                start: self.start,
+                end: self.end,
+            }
+            .validate()
+        }
+    }
+
+    // Both attributes take effect when they share a `#[prost(...)]`.
+    let valid = Descending { end: 2, start: 1 };
+    assert_eq!(valid.encode_to_vec(), b"\x10\x02\x08\x01");
+
+    let invalid = Descending { end: 1, start: 5 };
+    let error = Descending::decode(invalid.encode_to_vec().as_slice()).unwrap_err();
+    assert_eq!(error.kind(), DecodeErrorKind::ValidationFailed);
+}